@@ -0,0 +1,112 @@
+//! How fast bytes get through the stdio pipes, for tuning `MaxBufSize`: a bare `LockPipe` between
+//! a writer task and a reader task, and a module copying its stdin to its stdout. Smaller buffers
+//! keep less memory around per process, at the cost of more trips through the pipe's lock and
+//! more wakeups of the other end.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+use wasi_process2::{LockPipe, MaxBufSize, WasiProcessBuilder};
+
+/// How much is pushed through per iteration.
+const TOTAL: usize = 4 * 1024 * 1024;
+/// How much the writer hands over per write.
+const CHUNK: usize = 8 * 1024;
+/// The buffer sizes to compare.
+const BUF_SIZES: [usize; 3] = [1024, 16 * 1024, 64 * 1024];
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+/// Write `TOTAL` bytes in `CHUNK`s to `writer`, then close it.
+async fn produce(mut writer: impl tokio::io::AsyncWrite + Unpin) {
+    let chunk = vec![b'x'; CHUNK];
+    for _ in 0..TOTAL / CHUNK {
+        writer.write_all(&chunk).await.unwrap();
+    }
+    writer.shutdown().await.unwrap();
+}
+
+/// Read `reader` to the end, returning how much there was.
+async fn consume(mut reader: impl tokio::io::AsyncRead + Unpin) -> usize {
+    let mut buf = vec![0; CHUNK];
+    let mut total = 0;
+    loop {
+        match reader.read(&mut buf).await.unwrap() {
+            0 => return total,
+            n => total += n,
+        }
+    }
+}
+
+fn lock_pipe(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("lock_pipe");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+    for buf_size in BUF_SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buf_size),
+            &buf_size,
+            |b, &size| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let (reader, writer) = LockPipe::pair(size).unwrap();
+                        let writer = tokio::spawn(async move { produce(&writer).await });
+                        assert_eq!(consume(&reader).await, TOTAL);
+                        writer.await.unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn echo(c: &mut Criterion) {
+    let rt = runtime();
+    let store = wasmer::Store::default();
+    let engine = store.engine().clone();
+    let module = wasmer::Module::new(&store, include_str!("../echo.wat")).unwrap();
+    let mut group = c.benchmark_group("echo");
+    group.throughput(Throughput::Bytes(TOTAL as u64));
+    group.sample_size(20);
+    for buf_size in BUF_SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(buf_size),
+            &buf_size,
+            |b, &size| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let store = wasmer::Store::new(engine.clone());
+                        let mut wasi = WasiProcessBuilder::new("echo", module.clone())
+                            .max_buf_size(MaxBufSize {
+                                stdin: size,
+                                stdout: size,
+                                stderr: size,
+                            })
+                            .build(store)
+                            .unwrap();
+                        let stdin = wasi.stdin.take().unwrap();
+                        let stdout = wasi.stdout.take().unwrap();
+                        let handle = wasi.spawn();
+                        let (read, ()) = tokio::join!(consume(stdout), produce(stdin));
+                        // echo writes "END\n" after everything it read
+                        assert_eq!(read, TOTAL + 4);
+                        handle.await.unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lock_pipe, echo);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+(module
+  (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+  (memory 1)
+  (export "memory" (memory 0))
+
+  (data (i32.const 16) "END\n")
+
+  ;; copy stdin to stdout until EOF, then write "END\n"
+  (func $main (export "_start")
+        (loop $copy
+              (i32.store (i32.const 0) (i32.const 64))
+              (i32.store (i32.const 4) (i32.const 1024))
+              (call $fd_read
+                    (i32.const 0)
+                    (i32.const 0)
+                    (i32.const 1)
+                    (i32.const 8)
+               )
+              drop
+              (if (i32.load (i32.const 8))
+                  (then
+                   (i32.store (i32.const 4) (i32.load (i32.const 8)))
+                   (call $fd_write
+                         (i32.const 1)
+                         (i32.const 0)
+                         (i32.const 1)
+                         (i32.const 8)
+                    )
+                   drop
+                   (br $copy)
+                  )
+              )
+        )
+        (i32.store (i32.const 0) (i32.const 16))
+        (i32.store (i32.const 4) (i32.const 4))
+        (call $fd_write
+              (i32.const 1)
+              (i32.const 0)
+              (i32.const 1)
+              (i32.const 8)
+         )
+    drop
+  )
+)
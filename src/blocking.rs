@@ -0,0 +1,60 @@
+//! `std::io` adapters over stdin and stdout for blocking code, see
+//! [`WasiStdout::into_blocking_read`].
+
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Handle;
+
+use super::{WasiStdin, WasiStdout};
+
+/// Stdin as a `std::io::Write`, from [`WasiStdin::into_blocking_write`]. Each call blocks the
+/// thread until the module has made enough room for it. Dropping it closes stdin.
+pub struct BlockingStdin {
+    stdin: WasiStdin,
+    handle: Handle,
+}
+
+impl BlockingStdin {
+    pub(crate) fn new(stdin: WasiStdin, handle: Handle) -> Self {
+        Self { stdin, handle }
+    }
+
+    /// Get back the underlying stdin, to use it asynchronously again.
+    pub fn into_inner(self) -> WasiStdin {
+        self.stdin
+    }
+}
+
+impl io::Write for BlockingStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handle.block_on(self.stdin.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.handle.block_on(self.stdin.flush())
+    }
+}
+
+/// Stdout as a `std::io::Read`, from [`WasiStdout::into_blocking_read`]. Each call blocks the
+/// thread until the module has written something, or closed stdout.
+pub struct BlockingStdout {
+    stdout: WasiStdout,
+    handle: Handle,
+}
+
+impl BlockingStdout {
+    pub(crate) fn new(stdout: WasiStdout, handle: Handle) -> Self {
+        Self { stdout, handle }
+    }
+
+    /// Get back the underlying stdout, to use it asynchronously again.
+    pub fn into_inner(self) -> WasiStdout {
+        self.stdout
+    }
+}
+
+impl io::Read for BlockingStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.handle.block_on(self.stdout.read(buf))
+    }
+}
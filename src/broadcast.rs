@@ -0,0 +1,114 @@
+//! Sharing stdout with any number of readers, see [`WasiStdout::broadcast`].
+
+use bytes::{Buf, Bytes};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncRead, ReadBuf};
+use tokio::sync::broadcast::{self, error::RecvError, Receiver, Sender};
+
+use super::pipe::Mutex;
+use super::WasiStdout;
+
+/// Stdout shared between any number of [subscriptions](Self::subscribe), returned by
+/// [`WasiStdout::broadcast`]. A task reads everything from stdout as soon as the module writes it
+/// and sends each chunk to every subscription at the time.
+#[derive(Debug, Clone)]
+pub struct StdoutBroadcast {
+    /// Taken once stdout hits EOF, so that the subscriptions get EOF too.
+    sender: Arc<Mutex<Option<Sender<Bytes>>>>,
+}
+
+impl StdoutBroadcast {
+    /// Start getting everything the module writes from now on. A subscription made after stdout
+    /// has closed just gets EOF.
+    pub fn subscribe(&self) -> StdoutSubscription {
+        let recv = self
+            .sender
+            .lock()
+            .as_ref()
+            .map(|sender| recv(sender.subscribe()));
+        StdoutSubscription {
+            recv,
+            chunk: Bytes::new(),
+            missed: 0,
+        }
+    }
+}
+
+type RecvFuture = Pin<Box<dyn Future<Output = (Result<Bytes, RecvError>, Receiver<Bytes>)> + Send>>;
+
+fn recv(mut receiver: Receiver<Bytes>) -> RecvFuture {
+    Box::pin(async move { (receiver.recv().await, receiver) })
+}
+
+/// A reader for stdout that gets everything the module writes after it was created with
+/// [`StdoutBroadcast::subscribe`].
+///
+/// If a subscription falls more than the broadcast's capacity of chunks behind, it skips the
+/// oldest ones to catch up, as with a `tokio::sync::broadcast` channel; see
+/// [`missed`](Self::missed).
+pub struct StdoutSubscription {
+    /// `None` once the broadcast has closed.
+    recv: Option<RecvFuture>,
+    /// What's left of the last chunk received.
+    chunk: Bytes,
+    missed: u64,
+}
+
+impl StdoutSubscription {
+    /// How many chunks this subscription has skipped because it fell too far behind.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl AsyncRead for StdoutSubscription {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if self.chunk.has_remaining() {
+                let len = self.chunk.remaining().min(buf.remaining());
+                buf.put_slice(&self.chunk[..len]);
+                self.chunk.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+            let recv_fut = match &mut self.recv {
+                Some(recv_fut) => recv_fut,
+                None => return Poll::Ready(Ok(())),
+            };
+            let (res, receiver) = ready!(recv_fut.as_mut().poll(cx));
+            self.recv = Some(recv(receiver));
+            match res {
+                Ok(chunk) => self.chunk = chunk,
+                Err(RecvError::Lagged(missed)) => self.missed += missed,
+                Err(RecvError::Closed) => self.recv = None,
+            }
+        }
+    }
+}
+
+pub(crate) fn broadcast(source: WasiStdout, capacity: usize) -> StdoutBroadcast {
+    let (sender, _) = broadcast::channel(capacity);
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let pump_sender = sender.clone();
+    tokio::spawn(async move {
+        loop {
+            match source.read_bytes().await {
+                Ok(chunk) if !chunk.is_empty() => {
+                    if let Some(sender) = &*pump_sender.lock() {
+                        // it's fine if nobody's subscribed right now
+                        let _ = sender.send(chunk);
+                    }
+                }
+                _ => break,
+            }
+        }
+        pump_sender.lock().take();
+    });
+    StdoutBroadcast { sender }
+}
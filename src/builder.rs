@@ -0,0 +1,907 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+use wasmer::{AsStoreMut, BaseTunables, Engine, ExportError, InstantiationError, Module};
+use wasmer_wasi::{WasiError, WasiState, WasiStateCreationError};
+
+use super::memory::{LimitingTunables, MemoryLimit};
+use super::{
+    add_stdio_with, deterministic, turns, MaxBufSize, MemFs, NewError, Output, SpawnError, Stdio,
+    WasiProcess,
+};
+
+/// A builder for a [`WasiProcess`], which sets up the wasi state, the stdio pseudo-files, and
+/// the instance for a module.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use tokio::io::AsyncReadExt;
+/// use wasi_process2::WasiProcessBuilder;
+/// let mut store = wasmer::Store::default();
+/// let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let mut wasi = WasiProcessBuilder::new("progg", module)
+///     .args(&["foo", "bar"])
+///     .env("KEY", "value")
+///     .build(store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn();
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "Hello, World!\n");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct WasiProcessBuilder {
+    program_name: String,
+    module: Module,
+    args: Vec<Vec<u8>>,
+    envs: Vec<(Vec<u8>, Vec<u8>)>,
+    preopen_dirs: Vec<PathBuf>,
+    mem_fs: Option<MemFs>,
+    buf_size: MaxBufSize,
+    stdin: Stdio,
+    stdin_data: Option<Vec<u8>>,
+    stdin_file: Option<PathBuf>,
+    stdout: Stdio,
+    stderr: Stdio,
+    max_output_bytes: Option<u64>,
+    coalesce_writes: usize,
+    stderr_tail_bytes: Option<usize>,
+    timeout: Option<Duration>,
+    kill_on_drop: bool,
+    nonblocking_stdin: bool,
+    preallocate_buffers: bool,
+    max_memory_pages: Option<u32>,
+    /// The time for [`fixed_clock`](Self::fixed_clock), in nanoseconds since the epoch.
+    fixed_clock: Option<u64>,
+    random_seed: Option<u64>,
+    yield_interval: Option<NonZeroU64>,
+}
+
+impl WasiProcessBuilder {
+    /// Create a new builder for a process running `module`, with the given program name.
+    pub fn new(program_name: &str, module: Module) -> Self {
+        Self {
+            program_name: program_name.to_owned(),
+            module,
+            args: Vec::new(),
+            envs: Vec::new(),
+            preopen_dirs: Vec::new(),
+            mem_fs: None,
+            buf_size: MaxBufSize::default(),
+            stdin: Stdio::Piped,
+            stdin_data: None,
+            stdin_file: None,
+            stdout: Stdio::Piped,
+            stderr: Stdio::Piped,
+            max_output_bytes: None,
+            coalesce_writes: 0,
+            stderr_tail_bytes: None,
+            timeout: None,
+            kill_on_drop: false,
+            nonblocking_stdin: false,
+            preallocate_buffers: false,
+            max_memory_pages: None,
+            fixed_clock: None,
+            random_seed: None,
+            yield_interval: None,
+        }
+    }
+
+    /// Add an argument to pass to the module.
+    pub fn arg<Arg>(&mut self, arg: Arg) -> &mut Self
+    where
+        Arg: AsRef<[u8]>,
+    {
+        self.args.push(arg.as_ref().to_vec());
+        self
+    }
+
+    /// Add multiple arguments to pass to the module.
+    pub fn args<I, Arg>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Arg>,
+        Arg: AsRef<[u8]>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_vec()));
+        self
+    }
+
+    /// Add an environment variable to pass to the module.
+    pub fn env<Key, Value>(&mut self, key: Key, value: Value) -> &mut Self
+    where
+        Key: AsRef<[u8]>,
+        Value: AsRef<[u8]>,
+    {
+        self.envs
+            .push((key.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Preopen a host directory so the module can access it.
+    pub fn preopen_dir<P>(&mut self, dir: P) -> &mut Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.preopen_dirs.push(dir.into());
+        self
+    }
+
+    /// Give the module `fs` as its filesystem, preopened at `/`, instead of the host's, so it
+    /// can read and write files without touching the disk. This takes the place of any
+    /// [`preopen_dir`](Self::preopen_dir)s. Keep a clone of `fs` to read back what the module
+    /// wrote.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{MemFs, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies "input.txt" to "output.txt" in its preopened dir, which is fd 4 since wasmer puts
+    /// // a read-only root at fd 3
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "path_open" (func $path_open
+    ///         (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_close" (func $fd_close (param i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 100) "input.txt")
+    ///     (data (i32.const 120) "output.txt")
+    ///     (func (export "_start")
+    ///         ;; fd_read rights, no flags
+    ///         (if (call $path_open (i32.const 4) (i32.const 0) (i32.const 100) (i32.const 9)
+    ///                 (i32.const 0) (i64.const 2) (i64.const 0) (i32.const 0) (i32.const 200))
+    ///             (then unreachable))
+    ///         (i32.store (i32.const 0) (i32.const 1024))
+    ///         (i32.store (i32.const 4) (i32.const 1024))
+    ///         (drop (call $fd_read (i32.load (i32.const 200)) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///         ;; fd_write rights, created and truncated
+    ///         (if (call $path_open (i32.const 4) (i32.const 0) (i32.const 120) (i32.const 10)
+    ///                 (i32.const 9) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 204))
+    ///             (then unreachable))
+    ///         (i32.store (i32.const 4) (i32.load (i32.const 8)))
+    ///         (drop (call $fd_write (i32.load (i32.const 204)) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///         (drop (call $fd_close (i32.load (i32.const 200))))
+    ///         (drop (call $fd_close (i32.load (i32.const 204))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let fs = MemFs::new();
+    /// fs.write("/input.txt", b"copied in memory")?;
+    /// let wasi = WasiProcessBuilder::new("cp", module)
+    ///     .mem_fs(fs.clone())
+    ///     .build(store)?;
+    /// wasi.spawn().await?;
+    /// assert_eq!(fs.read("/output.txt")?, b"copied in memory");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn mem_fs(&mut self, fs: MemFs) -> &mut Self {
+        self.mem_fs = Some(fs);
+        self
+    }
+
+    /// Set the sizes of the internal stdio buffers.
+    pub fn max_buf_size(&mut self, buf_size: MaxBufSize) -> &mut Self {
+        self.buf_size = buf_size;
+        self
+    }
+
+    /// Limit how much the module can write to stdout and stderr combined, see
+    /// [`WasiProcess::limit_output_bytes`].
+    pub fn max_output_bytes(&mut self, max: u64) -> &mut Self {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    /// Collect the module's writes to stdout and stderr until there are `threshold` bytes of
+    /// them, see [`WasiProcess::coalesce_writes`].
+    pub fn coalesce_writes(&mut self, threshold: usize) -> &mut Self {
+        self.coalesce_writes = threshold;
+        self
+    }
+
+    /// Give the module at most `timeout` to run, see [`WasiProcess::timeout`].
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Kill the process when its handle is dropped before it's finished, see
+    /// [`WasiProcess::kill_on_drop`].
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Keep only the last `bytes` the module writes to stderr, see
+    /// [`WasiProcess::stderr_tail_bytes`].
+    pub fn stderr_tail_bytes(&mut self, bytes: usize) -> &mut Self {
+        self.stderr_tail_bytes = Some(bytes);
+        self
+    }
+
+    /// Have the module's stdin reads fail with `EAGAIN` rather than wait when there's nothing to
+    /// read, see [`WasiProcess::nonblocking_stdin`].
+    pub fn nonblocking_stdin(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking_stdin = nonblocking;
+        self
+    }
+
+    /// Allocate the stdio buffers up front, so writes don't have to grow them while the process
+    /// runs, see [`WasiProcess::preallocate_buffers`].
+    pub fn preallocate_buffers(&mut self, preallocate: bool) -> &mut Self {
+        self.preallocate_buffers = preallocate;
+        self
+    }
+
+    /// Limit the module's linear memory to `pages` wasm pages of 64 KiB each. A `memory.grow`
+    /// that would go past that fails like the wasm spec says a refused one does, returning -1 to
+    /// the module. Modules generally can't go on without the memory and trap, e.g. when their
+    /// allocator aborts, and a process that fails after being refused memory fails with
+    /// [`SpawnError::OutOfMemory`](super::SpawnError::OutOfMemory). A module that calls
+    /// `proc_exit` keeps its exit code, and one that copes with the failed grow keeps going. If
+    /// the module's memory starts out bigger than the limit, building fails with
+    /// [`BuildError::Instantiation`].
+    ///
+    /// wasmer sets how memories are created per engine, so with a limit, [`build`](Self::build)
+    /// creates the instance in a new store on a copy of the given store's engine, and the given
+    /// store is dropped.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{BuildError, SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // grows its memory a page at a time, and traps once that fails
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (loop $grow
+    ///             (br_if $grow (i32.ne (memory.grow (i32.const 1)) (i32.const -1))))
+    ///         unreachable))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("hungry", module.clone())
+    ///     .max_memory_pages(16)
+    ///     .build(store)?;
+    /// assert!(matches!(wasi.spawn().await, Err(SpawnError::OutOfMemory)));
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// let res = WasiProcessBuilder::new("hungry", module)
+    ///     .max_memory_pages(0)
+    ///     .build(store);
+    /// assert!(matches!(res, Err(BuildError::Instantiation(_))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_memory_pages(&mut self, pages: u32) -> &mut Self {
+        self.max_memory_pages = Some(pages);
+        self
+    }
+
+    /// Make every wasi clock read as `time`, for reproducible runs of modules that look at the
+    /// time, e.g. for snapshot tests of their output. Every clock also reports a resolution of
+    /// 1ns. Wasi times are nanoseconds since the epoch in a `u64`, so a `time` before the epoch
+    /// reads as the epoch, and one after that runs out, in 2554, reads as the last time it holds.
+    ///
+    /// The clocks don't move at all, so this is no good for a module that waits for time to
+    /// pass. Only `clock_time_get` and `clock_res_get` are replaced: a module that sleeps with
+    /// `poll_oneoff` still waits for the real time to pass, and then sees the same time as before.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::{Duration, SystemTime};
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // writes the realtime clock, 16 random bytes and the monotonic clock's resolution to
+    /// // stdout
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+    ///     (import "wasi_unstable" "clock_res_get" (func $clock_res_get (param i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "random_get" (func $random_get (param i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (drop (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 64)))
+    ///         (drop (call $random_get (i32.const 72) (i32.const 16)))
+    ///         (drop (call $clock_res_get (i32.const 1) (i32.const 88)))
+    ///         (i32.store (i32.const 0) (i32.const 64))
+    ///         (i32.store (i32.const 4) (i32.const 32))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    /// let mut builder = WasiProcessBuilder::new("clock", module);
+    /// builder.fixed_clock(time).random_seed(42);
+    ///
+    /// let first = builder.build(store)?.wait_with_output().await?;
+    /// # let store = wasmer::Store::new(engine.clone());
+    /// let second = builder.build(store)?.wait_with_output().await?;
+    /// assert_eq!(first.stdout[..8], 1_000_000_000_000_000_000u64.to_le_bytes());
+    /// assert_eq!(first.stdout[24..], 1u64.to_le_bytes());
+    /// assert_eq!(first.stdout, second.stdout);
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// // asks for 4 GiB of random bytes, exiting with the errno
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "random_get" (func $random_get (param i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (call $proc_exit (call $random_get (i32.const 0) (i32.const -1))))
+    ///     (func (param i64)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("greedy", module).random_seed(42).build(store)?;
+    /// // that's more than its memory holds, so it gets EFAULT rather than the host allocating it
+    /// assert_eq!(wasi.spawn().await?.code(), 21);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn fixed_clock(&mut self, time: SystemTime) -> &mut Self {
+        let nanos = match time.duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(since_epoch) => u64::try_from(since_epoch.as_nanos()).unwrap_or(u64::MAX),
+            // wasi can't tell the time before the epoch
+            Err(_) => 0,
+        };
+        self.fixed_clock = Some(nanos);
+        self
+    }
+
+    /// Make the module's wasi randomness come from a generator seeded with `seed`, so it's the
+    /// same on every run with the same seed. It's not cryptographically secure. See
+    /// [`fixed_clock`](Self::fixed_clock) for an example.
+    pub fn random_seed(&mut self, seed: u64) -> &mut Self {
+        self.random_seed = Some(seed);
+        self
+    }
+
+    /// Have the module take turns with the rest of the runtime: every `interval`th time it calls
+    /// wasi's `sched_yield`, e.g. through Rust's `std::thread::yield_now`, it waits there until
+    /// the task running the process has yielded to the runtime, like with
+    /// `tokio::task::yield_now`, and been polled again. That way a module that computes for a
+    /// long time doesn't keep other futures in the same task waiting, e.g. ones `join!`ed with
+    /// the process, and a process that's [paused](super::SpawnHandle::pause), killed or timed out
+    /// stops the module at its next yield, rather than it running on until `_start` returns.
+    ///
+    /// The module runs on tokio's blocking thread pool instead of with `block_in_place`, so this
+    /// works on a current-thread runtime too. A module that never calls `sched_yield` runs the
+    /// same as without this, in one go.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::num::NonZeroU64;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // calls sched_yield over and over, forever
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "sched_yield" (func $sched_yield (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (loop $spin (drop (call $sched_yield)) (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("spin", module)
+    ///     .yield_interval(NonZeroU64::new(1000).unwrap())
+    ///     .build(store)?;
+    /// let mut status = wasi.status_handle();
+    /// let kill = wasi.kill_handle();
+    /// // in the same task as the process, so it only runs while the module waits for its turn
+    /// let ticker = async {
+    ///     for _ in 0..10 {
+    ///         tokio::task::yield_now().await;
+    ///     }
+    ///     kill.kill();
+    /// };
+    /// let (res, ()) = tokio::join!(wasi, ticker);
+    /// assert!(res.is_err());
+    /// // the module ended at its next yield once it was killed
+    /// assert!(matches!(status.wait().await, Some(Err(SpawnError::Killed))));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn yield_interval(&mut self, interval: NonZeroU64) -> &mut Self {
+        self.yield_interval = Some(interval);
+        self
+    }
+
+    /// Set how the module's stdin is set up. If it's [`Stdio::Null`] or [`Stdio::Inherit`], the
+    /// process's `stdin` is `None`.
+    pub fn stdin(&mut self, stdin: Stdio) -> &mut Self {
+        self.stdin = stdin;
+        self.stdin_data = None;
+        self.stdin_file = None;
+        self
+    }
+
+    /// Give the module `data` as its entire stdin: it reads `data` and then EOF, and the
+    /// process's `stdin` is `None`. This replaces any earlier [`stdin`](Self::stdin) setting, and
+    /// `data` may be bigger than the stdin buffer size.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let mut store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .stdin_data("hello")
+    ///     .build(store)?;
+    /// assert!(wasi.stdin.is_none());
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "helloEND\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stdin_data<D>(&mut self, data: D) -> &mut Self
+    where
+        D: Into<Vec<u8>>,
+    {
+        self.stdin = Stdio::Piped;
+        self.stdin_data = Some(data.into());
+        self.stdin_file = None;
+        self
+    }
+
+    /// Give the module the contents of the file at `path` as its stdin, e.g. input recorded
+    /// for a regression test: it's streamed into stdin as the module reads it, and then stdin is
+    /// closed, like [`WasiProcess::feed_stdin`]. The process's `stdin` is `None`, and this
+    /// replaces any earlier [`stdin`](Self::stdin) setting.
+    ///
+    /// The file is opened when the process is built, which fails with a
+    /// [`BuildError::StdinFile`] if it can't be, and which then has to be done from within a
+    /// tokio runtime.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{BuildError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// let path = std::env::temp_dir().join("wasi-process2-stdin-from-file.txt");
+    /// std::fs::write(&path, "recorded input\n".repeat(1000))?;
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let wasi = WasiProcessBuilder::new("echo", module.clone())
+    ///     .stdin_from_file(&path)
+    ///     .build(store)?;
+    /// assert!(wasi.stdin.is_none());
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.stdout, [std::fs::read(&path)?, b"END\n".to_vec()].concat());
+    /// std::fs::remove_file(&path)?;
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// let res = WasiProcessBuilder::new("echo", module)
+    ///     .stdin_from_file(&path)
+    ///     .build(store);
+    /// let not_found = |e: &std::io::Error| e.kind() == std::io::ErrorKind::NotFound;
+    /// assert!(matches!(res, Err(BuildError::StdinFile(e)) if not_found(&e)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stdin_from_file<P>(&mut self, path: P) -> &mut Self
+    where
+        P: Into<PathBuf>,
+    {
+        self.stdin = Stdio::Piped;
+        self.stdin_data = None;
+        self.stdin_file = Some(path.into());
+        self
+    }
+
+    /// Set how the module's stdout is set up. If it's [`Stdio::Null`] or [`Stdio::Inherit`], the
+    /// process's `stdout` is `None`.
+    ///
+    /// # Examples
+    /// A module that only writes to stdout doesn't need the other streams:
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{Stdio, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module)
+    ///     .stdin(Stdio::null())
+    ///     .stdout(Stdio::piped())
+    ///     .stderr(Stdio::null())
+    ///     .build(store)?;
+    /// assert!(wasi.stdin.is_none());
+    /// assert!(wasi.stderr.is_none());
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stdout(&mut self, stdout: Stdio) -> &mut Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// Set how the module's stderr is set up. If it's anything but [`Stdio::Piped`], the
+    /// process's `stderr` is `None`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{Stdio, WasiProcessBuilder};
+    /// # let mut store = wasmer::Store::default();
+    /// // writes 64 bytes to stderr 1000 times, way more than fits in the buffer
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 64))
+    ///         (loop $write
+    ///             (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 1000))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("noisy", module)
+    ///     .stderr(Stdio::null())
+    ///     .build(store)?;
+    /// assert!(wasi.stderr.is_none());
+    /// wasi.spawn().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stderr(&mut self, stderr: Stdio) -> &mut Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Instantiate the module with the configured wasi state and create the process.
+    /// `store` has to use the engine the module was compiled with; with the `cranelift` or
+    /// `singlepass` feature, `wasi_process2::store()` makes one with that compiler.
+    ///
+    /// If any stream is set to [`Stdio::Inherit`], or stdin comes from
+    /// [a file](Self::stdin_from_file), this spawns the task copying it, so it has to be called
+    /// from within a tokio runtime. Copying the host's stdin stops once the process is done, but
+    /// a read of it that's already underway can still keep the runtime from shutting down until
+    /// the next line comes in.
+    pub fn build(
+        &mut self,
+        store: impl AsStoreMut + Send + Sync + 'static,
+    ) -> Result<WasiProcess, BuildError> {
+        let pages = match self.max_memory_pages {
+            Some(pages) => pages,
+            None => return self.build_in(store),
+        };
+        let limit = Arc::new(MemoryLimit::new(pages));
+        let mut engine = store.as_store_ref().engine().clone();
+        let base = BaseTunables::for_target(engine.target());
+        engine.set_tunables(LimitingTunables::new(base, limit.clone()));
+        let mut process = self.build_in(wasmer::Store::new(engine))?;
+        process.memory_limit = Some(limit);
+        Ok(process)
+    }
+
+    fn build_in(
+        &mut self,
+        mut store: impl AsStoreMut + Send + Sync + 'static,
+    ) -> Result<WasiProcess, BuildError> {
+        // before the module is instantiated, so a missing file fails fast
+        let stdin_file = match &self.stdin_file {
+            Some(path) => Some(std::fs::File::open(path).map_err(BuildError::StdinFile)?),
+            None => None,
+        };
+        let mut state = WasiState::new(&self.program_name);
+        add_stdio_with(&mut state, self.stdin, self.stdout, self.stderr)
+            .args(&self.args)
+            .envs(self.envs.iter().map(|(k, v)| (k, v)));
+        match &self.mem_fs {
+            Some(fs) => {
+                state.set_fs(fs.backing()).preopen_dir("/")?;
+            }
+            None => {
+                state.preopen_dirs(&self.preopen_dirs)?;
+            }
+        }
+        let mut env = state.finalize(&mut store)?;
+        let mut imports = env.import_object(&mut store, &self.module)?;
+        if let Some(nanos) = self.fixed_clock {
+            deterministic::fixed_clock(&mut store, &env, &mut imports, nanos);
+        }
+        if let Some(seed) = self.random_seed {
+            deterministic::seeded_random(&mut store, &env, &mut imports, seed);
+        }
+        if self.yield_interval.is_some() {
+            turns::sched_yield(&mut store, &env, &mut imports);
+        }
+        let instance = wasmer::Instance::new(&mut store, &self.module, &imports)?;
+        env.initialize(&mut store, &instance)?;
+        let mut process = WasiProcess::new(store, &instance, self.buf_size)?;
+        process.set_name(&self.program_name);
+        process.yield_interval = self.yield_interval;
+        if let Some(max) = self.max_output_bytes {
+            process.limit_output_bytes(max);
+        }
+        process.coalesce_writes(self.coalesce_writes);
+        if let Some(bytes) = self.stderr_tail_bytes {
+            process.stderr_tail_bytes(bytes);
+        }
+        if let Some(timeout) = self.timeout {
+            process.timeout(timeout);
+        }
+        process.kill_on_drop(self.kill_on_drop);
+        process.nonblocking_stdin(self.nonblocking_stdin);
+        match self.stdin {
+            Stdio::Null => process.stdin = None,
+            // like a fed stdin, so the copy stops once the process is done
+            Stdio::Inherit => process.feed_stdin(io::stdin()),
+            Stdio::Piped | Stdio::ToStdout => {}
+        }
+        if let Some(data) = &self.stdin_data {
+            process.seed_stdin(data, self.buf_size.stdin);
+        }
+        if let Some(file) = stdin_file {
+            process.feed_stdin(tokio::fs::File::from_std(file));
+        }
+        if self.preallocate_buffers {
+            process.preallocate_buffers();
+        }
+        match self.stdout {
+            Stdio::Null => process.stdout = None,
+            Stdio::Inherit => {
+                if let Some(stdout) = process.stdout.take() {
+                    tokio::spawn(inherit(stdout, io::stdout()));
+                }
+            }
+            Stdio::Piped | Stdio::ToStdout => {}
+        }
+        match self.stderr {
+            Stdio::Null | Stdio::ToStdout => process.stderr = None,
+            Stdio::Inherit => {
+                if let Some(stderr) = process.stderr.take() {
+                    tokio::spawn(inherit(stderr, io::stderr()));
+                }
+            }
+            Stdio::Piped => {}
+        }
+        Ok(process)
+    }
+}
+
+/// Settings for a process that can be kept around and reused for any number of processes, e.g.
+/// from a config file. [`build`](Self::build) applies all of them at once, along with
+/// [`add_stdio`](super::add_stdio), so none of them can be forgotten.
+///
+/// Environment variable keys can't contain `=`; building fails with a
+/// [`BuildError::State`] if one does.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::AsyncReadExt;
+/// use wasi_process2::{BuildError, ProcessConfig};
+/// # let store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// // writes its arguments to stdout, each followed by a nul byte
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (import "wasi_unstable" "args_sizes_get" (func $args_sizes_get (param i32 i32) (result i32)))
+///     (import "wasi_unstable" "args_get" (func $args_get (param i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start")
+///         (drop (call $args_sizes_get (i32.const 100) (i32.const 4)))
+///         (drop (call $args_get (i32.const 200) (i32.const 1024)))
+///         (i32.store (i32.const 0) (i32.const 1024))
+///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+///     (func (param i64)))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let config = ProcessConfig {
+///     args: vec!["foo".into(), "bar".into()],
+///     env: vec![("KEY".into(), "value".into())],
+///     ..ProcessConfig::default()
+/// };
+/// let mut wasi = config.build("progg", module.clone(), store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn();
+/// let mut out = Vec::new();
+/// stdout.read_to_end(&mut out).await?;
+/// assert_eq!(out, b"progg\0foo\0bar\0");
+///
+/// let bad = ProcessConfig {
+///     env: vec![("KEY=".into(), "value".into())],
+///     ..ProcessConfig::default()
+/// };
+/// let res = bad.build("progg", module, wasmer::Store::new(engine));
+/// assert!(matches!(res, Err(BuildError::State(_))));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProcessConfig {
+    /// The arguments passed to the module, after the program name
+    pub args: Vec<String>,
+    /// The environment variables passed to the module, as key-value pairs
+    pub env: Vec<(String, String)>,
+    /// The host directories the module can access
+    pub preopen_dirs: Vec<PathBuf>,
+    /// The sizes of the internal stdio buffers
+    pub buf_size: MaxBufSize,
+    /// How long the module can run for, see [`WasiProcess::timeout`]
+    pub timeout: Option<Duration>,
+}
+
+impl ProcessConfig {
+    /// Create a [`WasiProcessBuilder`] with these settings, to set anything else before building.
+    pub fn builder(&self, program_name: &str, module: Module) -> WasiProcessBuilder {
+        let mut builder = WasiProcessBuilder::new(program_name, module);
+        builder.args(&self.args).max_buf_size(self.buf_size);
+        for (key, value) in &self.env {
+            builder.env(key, value);
+        }
+        for dir in &self.preopen_dirs {
+            builder.preopen_dir(dir);
+        }
+        if let Some(timeout) = self.timeout {
+            builder.timeout(timeout);
+        }
+        builder
+    }
+
+    /// Instantiate `module` with these settings and create the process.
+    pub fn build(
+        &self,
+        program_name: &str,
+        module: Module,
+        store: impl AsStoreMut + Send + Sync + 'static,
+    ) -> Result<WasiProcess, BuildError> {
+        self.builder(program_name, module).build(store)
+    }
+}
+
+/// A module with the settings to run it with, for running it any number of times, e.g. once per
+/// request in a server. The module is only compiled once, when it's created; every run gets a
+/// fresh store and instance, along with its own pipes, so nothing the module does in one run,
+/// like changing its memory or globals, carries over to the next.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use wasi_process2::{ProcessConfig, WasiProcessTemplate};
+/// let store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let template =
+///     WasiProcessTemplate::new("echo", module, store.engine().clone(), ProcessConfig::default());
+/// let first = template.spawn_run(b"hello\n")?;
+/// let second = template.spawn_run(b"goodbye\n")?;
+/// assert_eq!(first.await?.stdout, b"hello\nEND\n");
+/// assert_eq!(second.await?.stdout, b"goodbye\nEND\n");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct WasiProcessTemplate {
+    program_name: String,
+    module: Module,
+    engine: Engine,
+    config: ProcessConfig,
+}
+
+impl fmt::Debug for WasiProcessTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WasiProcessTemplate")
+            .field("program_name", &self.program_name)
+            .field("module", &self.module)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WasiProcessTemplate {
+    /// Create a template for running `module`, which has to have been compiled with `engine`,
+    /// with the given program name and settings.
+    pub fn new(program_name: &str, module: Module, engine: Engine, config: ProcessConfig) -> Self {
+        Self {
+            program_name: program_name.to_owned(),
+            module,
+            engine,
+            config,
+        }
+    }
+
+    /// Instantiate the module in a new store, and spawn it with `input` on stdin. The returned
+    /// future resolves to everything it wrote, like [`WasiProcess::wait_with_output`]; the run
+    /// goes ahead whether or not it's awaited. This has to be called from within a tokio runtime.
+    pub fn spawn_run(
+        &self,
+        input: &[u8],
+    ) -> Result<impl Future<Output = Result<Output, SpawnError>> + Send + 'static, BuildError> {
+        let store = wasmer::Store::new(self.engine.clone());
+        let process = self
+            .config
+            .builder(&self.program_name, self.module.clone())
+            .stdin_data(input)
+            .build(store)?;
+        let run = tokio::spawn(process.wait_with_output());
+        Ok(async move { run.await.map_err(SpawnError::from_join)? })
+    }
+}
+
+/// Copy everything from `from` to `to` until either end is closed, for [`Stdio::Inherit`].
+async fn inherit(mut from: impl AsyncRead + Unpin, mut to: impl AsyncWrite + Unpin) {
+    let _ = io::copy(&mut from, &mut to).await;
+    let _ = to.shutdown().await;
+}
+
+/// An error encountered while building a [`WasiProcess`] with a [`WasiProcessBuilder`].
+#[derive(Debug)]
+pub enum BuildError {
+    /// An error creating the wasi state
+    State(WasiStateCreationError),
+    /// An error generating the wasi imports for the module
+    Wasi(WasiError),
+    /// An error instantiating the module
+    Instantiation(Box<InstantiationError>),
+    /// A missing export needed by wasi, such as `memory`
+    Export(ExportError),
+    /// An error creating the process from the instance, such as a missing `_start`
+    Process(NewError),
+    /// An error opening the file for [`WasiProcessBuilder::stdin_from_file`]
+    StdinFile(std::io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::State(e) => write!(f, "error creating the wasi state: {}", e),
+            Self::Wasi(e) => write!(f, "error generating wasi imports: {}", e),
+            Self::Instantiation(e) => write!(f, "error instantiating the module: {}", e),
+            Self::Export(e) => write!(f, "missing export: {}", e),
+            Self::Process(e) => write!(f, "error creating the process: {}", e),
+            Self::StdinFile(e) => write!(f, "error opening the stdin file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<WasiStateCreationError> for BuildError {
+    fn from(e: WasiStateCreationError) -> Self {
+        Self::State(e)
+    }
+}
+
+impl From<WasiError> for BuildError {
+    fn from(e: WasiError) -> Self {
+        Self::Wasi(e)
+    }
+}
+
+impl From<InstantiationError> for BuildError {
+    fn from(e: InstantiationError) -> Self {
+        Self::Instantiation(Box::new(e))
+    }
+}
+
+impl From<ExportError> for BuildError {
+    fn from(e: ExportError) -> Self {
+        Self::Export(e)
+    }
+}
+
+impl From<NewError> for BuildError {
+    fn from(e: NewError) -> Self {
+        Self::Process(e)
+    }
+}
@@ -0,0 +1,148 @@
+//! Driving a request/response module one exchange at a time, see [`WasiProcess::converse`].
+
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use super::{SpawnError, SpawnHandle, WasiProcess, WasiStdin, WasiStdout};
+
+/// The responses of a process to a stream of inputs, from [`WasiProcess::converse`].
+pub struct Conversation<S> {
+    /// `None` once the inputs have ended, or the module has stopped reading them.
+    inputs: Option<S>,
+    stdin: Option<WasiStdin>,
+    stdout: Option<WasiStdout>,
+    handle: SpawnHandle,
+    /// The rest of the input that's being written.
+    writing: Option<Bytes>,
+    /// Whether an input has been written and its response hasn't been returned yet.
+    awaiting_response: bool,
+    delimiter: Option<u8>,
+    /// The bytes read from stdout that aren't part of a returned response yet.
+    buf: BytesMut,
+    /// Whether stdout has hit EOF.
+    eof: bool,
+}
+
+impl<S> Conversation<S>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    pub(crate) fn new(mut process: WasiProcess, inputs: S) -> Self {
+        let stdin = process.stdin.take();
+        let stdout = process.stdout.take();
+        Self {
+            inputs: stdin.is_some().then_some(inputs),
+            eof: stdout.is_none(),
+            stdin,
+            stdout,
+            handle: process.spawn(),
+            writing: None,
+            awaiting_response: false,
+            delimiter: None,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// End each response at `delimiter`, including it, rather than at whatever the module has
+    /// written so far, for a module that might write a response in more than one go.
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.delimiter = Some(delimiter);
+        self
+    }
+
+    /// Write the next input and get the module's response to it, or `None` once the
+    /// conversation is over.
+    pub async fn next_response(&mut self) -> Option<Bytes> {
+        poll_fn(|cx| self.poll_response(cx)).await
+    }
+
+    fn poll_response(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        loop {
+            if let Some(data) = &mut self.writing {
+                let stdin = self.stdin.as_ref().expect("there's only input with stdin");
+                match ready!(stdin.inner.poll_write_bytes(cx, data)) {
+                    Ok(_) if !data.is_empty() => continue,
+                    Ok(_) => self.awaiting_response = true,
+                    // the module is done reading, so there's no point in writing the rest
+                    Err(_) => {
+                        self.inputs = None;
+                        self.stdin = None;
+                    }
+                }
+                self.writing = None;
+            }
+            if self.awaiting_response || self.inputs.is_none() {
+                if let Some(response) = self.take_response() {
+                    self.awaiting_response = false;
+                    return Poll::Ready(Some(response));
+                }
+                if self.eof {
+                    return Poll::Ready(None);
+                }
+                let stdout = self
+                    .stdout
+                    .as_ref()
+                    .expect("there's only output with stdout");
+                let chunk = ready!(stdout.inner.poll_read_bytes(cx, usize::MAX));
+                if chunk.is_empty() {
+                    self.eof = true;
+                } else {
+                    self.buf.extend_from_slice(&chunk);
+                }
+                continue;
+            }
+            let inputs = self.inputs.as_mut().expect("checked above");
+            match ready!(Pin::new(inputs).poll_next(cx)) {
+                Some(input) => self.writing = Some(input),
+                None => {
+                    // closing stdin lets a module that reads until EOF finish, and whatever it
+                    // writes after that still comes out as responses
+                    self.inputs = None;
+                    self.stdin = None;
+                }
+            }
+        }
+    }
+
+    /// Split a response off the front of `buf`, if there's a whole one there.
+    fn take_response(&mut self) -> Option<Bytes> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        let len = match self.delimiter {
+            Some(delimiter) => match self.buf.iter().position(|&b| b == delimiter) {
+                Some(i) => i + 1,
+                // whatever's left at EOF is the last response, even without a delimiter
+                None if self.eof => self.buf.len(),
+                None => return None,
+            },
+            None => self.buf.len(),
+        };
+        Some(self.buf.split_to(len).freeze())
+    }
+
+    /// End the conversation and wait for the process to finish, getting its exit code like
+    /// [`Output::status`](super::Output), or the error it failed with. Stdin is closed, and
+    /// stdout is closed too, throwing away any responses that haven't been read, so the module
+    /// gets an error if it keeps writing.
+    pub async fn wait(mut self) -> Result<i32, SpawnError> {
+        drop(self.stdin.take());
+        if let Some(stdout) = self.stdout.take() {
+            stdout.close();
+        }
+        self.handle.await.map(|status| status.code())
+    }
+}
+
+impl<S> Stream for Conversation<S>
+where
+    S: Stream<Item = Bytes> + Unpin,
+{
+    type Item = Bytes;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_response(cx)
+    }
+}
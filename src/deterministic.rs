@@ -0,0 +1,90 @@
+//! Replacements for the wasi clock and randomness imports, for reproducible runs, see
+//! [`WasiProcessBuilder::fixed_clock`](super::WasiProcessBuilder::fixed_clock) and
+//! [`WasiProcessBuilder::random_seed`](super::WasiProcessBuilder::random_seed).
+
+use std::sync::Arc;
+use wasmer::{AsStoreMut, Function, FunctionEnvMut, Imports};
+use wasmer_wasi::types::wasi::Errno;
+use wasmer_wasi::{WasiEnv, WasiFunctionEnv};
+
+use super::pipe::Mutex;
+
+/// Both of the namespaces the wasi imports can come from.
+pub(crate) const NAMESPACES: [&str; 2] = ["wasi_unstable", "wasi_snapshot_preview1"];
+
+/// Replace `clock_time_get` with one that always returns `nanos` for every clock, and
+/// `clock_res_get` with one that always returns a resolution of 1ns, since the real resolution
+/// depends on the host.
+pub(crate) fn fixed_clock(
+    store: &mut impl AsStoreMut,
+    env: &WasiFunctionEnv,
+    imports: &mut Imports,
+    nanos: u64,
+) {
+    let clock_time_get = move |ctx: FunctionEnvMut<WasiEnv>, clock_id: i32, _: i64, out: i32| {
+        if !(0..4).contains(&clock_id) {
+            return Errno::Inval as i32;
+        }
+        write(&ctx, out, &nanos.to_le_bytes())
+    };
+    let clock_res_get = |ctx: FunctionEnvMut<WasiEnv>, clock_id: i32, out: i32| {
+        if !(0..4).contains(&clock_id) {
+            return Errno::Inval as i32;
+        }
+        write(&ctx, out, &1u64.to_le_bytes())
+    };
+    for namespace in NAMESPACES {
+        let function = Function::new_typed_with_env(store, &env.env, clock_time_get);
+        imports.define(namespace, "clock_time_get", function);
+        let function = Function::new_typed_with_env(store, &env.env, clock_res_get);
+        imports.define(namespace, "clock_res_get", function);
+    }
+}
+
+/// Replace `random_get` with one that fills the buffer from a PRNG seeded with `seed`.
+pub(crate) fn seeded_random(
+    store: &mut impl AsStoreMut,
+    env: &WasiFunctionEnv,
+    imports: &mut Imports,
+    seed: u64,
+) {
+    // shared between the namespaces, so the sequence is the same whichever one the module uses
+    let state = Arc::new(Mutex::new(seed));
+    for namespace in NAMESPACES {
+        let state = state.clone();
+        let random_get = move |ctx: FunctionEnvMut<WasiEnv>, buf: i32, len: i32| {
+            // the module picks `len`, so make sure it fits in its memory before allocating that
+            let end = buf as u32 as u64 + len as u32 as u64;
+            if end > ctx.data().memory_view(&ctx).data_size() {
+                return Errno::Fault as i32;
+            }
+            let mut bytes = vec![0; len as u32 as usize];
+            let mut state = state.lock();
+            for chunk in bytes.chunks_mut(8) {
+                let n = chunk.len();
+                chunk.copy_from_slice(&splitmix64(&mut state).to_le_bytes()[..n]);
+            }
+            write(&ctx, buf, &bytes)
+        };
+        let function = Function::new_typed_with_env(store, &env.env, random_get);
+        imports.define(namespace, "random_get", function);
+    }
+}
+
+/// Write `bytes` to the module's memory at `ptr`, returning the errno for the import.
+fn write(ctx: &FunctionEnvMut<WasiEnv>, ptr: i32, bytes: &[u8]) -> i32 {
+    let memory = ctx.data().memory_view(ctx);
+    match memory.write(ptr as u32 as u64, bytes) {
+        Ok(()) => Errno::Success as i32,
+        Err(_) => Errno::Fault as i32,
+    }
+}
+
+/// The next number from a SplitMix64 generator, which is tiny and plenty for reproducibility.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,135 @@
+//! Length-prefixed framing on top of stdin and stdout, see [`WasiStdin::framed`].
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures_core::Stream;
+use std::convert::TryFrom;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{self, AsyncWriteExt};
+
+use super::{WasiStdin, WasiStdout};
+
+/// The byte order of the `u32` length prefix of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    /// Most significant byte first, i.e. network byte order
+    Big,
+    /// Least significant byte first
+    Little,
+}
+
+impl Endian {
+    fn encode(self, len: u32) -> [u8; 4] {
+        match self {
+            Self::Big => len.to_be_bytes(),
+            Self::Little => len.to_le_bytes(),
+        }
+    }
+
+    fn decode(self, prefix: [u8; 4]) -> u32 {
+        match self {
+            Self::Big => u32::from_be_bytes(prefix),
+            Self::Little => u32::from_le_bytes(prefix),
+        }
+    }
+}
+
+/// Stdin, written to one frame at a time, from [`WasiStdin::framed`].
+pub struct FramedStdin {
+    stdin: WasiStdin,
+    endian: Endian,
+}
+
+impl FramedStdin {
+    pub(crate) fn new(stdin: WasiStdin, endian: Endian) -> Self {
+        Self { stdin, endian }
+    }
+
+    /// Write `frame` with its length in front of it. Returns an `InvalidInput` error without
+    /// writing anything if the length doesn't fit in a `u32`.
+    pub async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let len = u32::try_from(frame.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is too long for a u32 length prefix",
+            )
+        })?;
+        self.stdin.write_all(&self.endian.encode(len)).await?;
+        self.stdin.write_all(frame).await
+    }
+
+    /// Get back the underlying stdin, e.g. to shut it down.
+    pub fn into_inner(self) -> WasiStdin {
+        self.stdin
+    }
+}
+
+/// Stdout, read one frame at a time, from [`WasiStdout::framed`].
+pub struct FramedStdout {
+    stdout: WasiStdout,
+    endian: Endian,
+    /// The bytes read from stdout that aren't part of a returned frame yet.
+    buf: BytesMut,
+    /// Whether stdout has hit EOF.
+    eof: bool,
+}
+
+impl FramedStdout {
+    pub(crate) fn new(stdout: WasiStdout, endian: Endian) -> Self {
+        Self {
+            stdout,
+            endian,
+            buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    /// Get the next frame, without its length prefix, or `None` at EOF. If stdout ends partway
+    /// through a frame, this returns an `UnexpectedEof` error, and then `None`.
+    pub async fn next_frame(&mut self) -> io::Result<Option<Bytes>> {
+        poll_fn(|cx| self.poll_frame(cx)).await.transpose()
+    }
+
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        loop {
+            if self.buf.len() >= 4 {
+                let prefix = [self.buf[0], self.buf[1], self.buf[2], self.buf[3]];
+                let len = self.endian.decode(prefix) as usize;
+                if self.buf.len() - 4 >= len {
+                    self.buf.advance(4);
+                    return Poll::Ready(Some(Ok(self.buf.split_to(len).freeze())));
+                }
+            }
+            if self.eof {
+                if self.buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                self.buf.clear();
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stdout ended in the middle of a frame",
+                ))));
+            }
+            let chunk = ready!(self.stdout.inner.poll_read_bytes(cx, usize::MAX));
+            if chunk.is_empty() {
+                self.eof = true;
+            } else {
+                self.buf.extend_from_slice(&chunk);
+            }
+        }
+    }
+
+    /// Get back the underlying stdout. Anything that's been read from it but isn't part of a
+    /// returned frame yet is lost.
+    pub fn into_inner(self) -> WasiStdout {
+        self.stdout
+    }
+}
+
+impl Stream for FramedStdout {
+    type Item = io::Result<Bytes>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_frame(cx)
+    }
+}
@@ -0,0 +1,104 @@
+//! Hooking a process up to the host's streams for an interactive session, see [`interact`].
+
+use std::convert::Infallible;
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use super::{SpawnError, WasiProcess};
+
+/// Run `process` as an interactive session, e.g. a REPL: copy `host_in` to its stdin, and its
+/// stdout and stderr to `host_out` and `host_err`, all at the same time, until it finishes.
+/// Returns the exit code of the process like [`Output::status`](super::Output), or the error it
+/// failed with.
+///
+/// When `host_in` hits EOF, the module's stdin is closed, so a module that reads until EOF
+/// finishes. If the module finishes first, copying `host_in` is cancelled, since nothing is
+/// going to read it anymore. Either way, everything the module wrote is copied out before this
+/// returns. Streams that were already taken from the process are skipped. If writing an output
+/// stream to the host fails, that stream is closed, so the module gets an error for it too.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+/// use wasi_process2::WasiProcessBuilder;
+/// # let store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+/// let (mut user_in, host_in) = tokio::io::duplex(64);
+/// let (host_out, user_out) = tokio::io::duplex(64);
+/// let session = async {
+///     let mut user_out = BufReader::new(user_out);
+///     let mut line = String::new();
+///     for input in ["hello\n", "is anyone there?\n"] {
+///         user_in.write_all(input.as_bytes()).await?;
+///         line.clear();
+///         user_out.read_line(&mut line).await?;
+///         assert_eq!(line, input);
+///     }
+///     drop(user_in);
+///     let mut rest = String::new();
+///     user_out.read_to_string(&mut rest).await?;
+///     assert_eq!(rest, "END\n");
+///     Ok::<_, std::io::Error>(())
+/// };
+/// let interact = wasi_process2::interact(wasi, host_in, host_out, tokio::io::sink());
+/// let (status, session) = tokio::join!(interact, session);
+/// assert_eq!(status?, 0);
+/// session?;
+///
+/// // never reads stdin, so the endless input is cut off once it's done
+/// # let store = wasmer::Store::new(engine);
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+/// let mut out = Vec::new();
+/// let input = tokio::io::repeat(b'a');
+/// let status = wasi_process2::interact(wasi, input, &mut out, tokio::io::sink()).await?;
+/// assert_eq!(status, 0);
+/// assert_eq!(out, b"Hello, World!\n");
+/// # Ok(())
+/// # }
+/// ```
+pub async fn interact(
+    mut process: WasiProcess,
+    mut host_in: impl AsyncRead + Unpin,
+    mut host_out: impl AsyncWrite + Unpin,
+    mut host_err: impl AsyncWrite + Unpin,
+) -> Result<i32, SpawnError> {
+    let stdin = process.stdin.take();
+    let stdout = process.stdout.take();
+    let stderr = process.stderr.take();
+    let handle = process.spawn();
+
+    let feed = async {
+        if let Some(mut stdin) = stdin {
+            if io::copy(&mut host_in, &mut stdin).await.is_ok() {
+                let _ = stdin.shutdown().await;
+            }
+        }
+        // only the process finishing ends the session
+        std::future::pending::<Infallible>().await
+    };
+    let run = async {
+        tokio::select! {
+            res = handle => res,
+            never = feed => match never {},
+        }
+    };
+    let (res, (), ()) = tokio::join!(
+        run,
+        copy_out(stdout, &mut host_out),
+        copy_out(stderr, &mut host_err)
+    );
+    res.map(|status| status.code())
+}
+
+/// Copy everything from `from` to `to`, if there's anything to copy from, and flush `to`.
+async fn copy_out(from: Option<impl AsyncRead + Unpin>, to: &mut (impl AsyncWrite + Unpin)) {
+    if let Some(mut from) = from {
+        // if the host end fails, `from` is dropped, so the module's writes fail too
+        let _ = io::copy(&mut from, to).await;
+        let _ = to.flush().await;
+    }
+}
@@ -3,21 +3,19 @@
 //! ```
 //! # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
 //! # use tokio::io::AsyncReadExt;
-//! use wasmer_wasi::{WasiEnv, WasiState, WasiVersion};
-//! use wasi_process::WasiProcess;
-//! let store = wasmer::Store::default();
+//! use wasmer_wasi::WasiState;
+//! use wasi_process2::WasiProcess;
+//! let mut store = wasmer::Store::default();
 //! let wasm = include_bytes!("../helloworld.wasm"); // just write(1, "Hello, World!\n", 14)
 //! let module = wasmer::Module::new(&store, wasm)?;
 //! let mut state = WasiState::new("progg");
-//! wasi_process::add_stdio(&mut state);
+//! wasi_process2::add_stdio(&mut state);
 //! state.args(&["foo", "bar"]);
-//! let imports = wasmer_wasi::generate_import_object_from_env(
-//!     &store,
-//!     WasiEnv::new(state.build()?),
-//!     wasmer_wasi::get_wasi_version(&module, false).unwrap_or(WasiVersion::Latest),
-//! );
-//! let instance = wasmer::Instance::new(&module, &imports)?;
-//! let mut wasi = WasiProcess::new(&instance, wasi_process::MaxBufSize::default())?;
+//! let mut env = state.finalize(&mut store)?;
+//! let imports = env.import_object(&mut store, &module)?;
+//! let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+//! env.initialize(&mut store, &instance)?;
+//! let mut wasi = WasiProcess::new(store, &instance, wasi_process2::MaxBufSize::default())?;
 //! let mut stdout = wasi.stdout.take().unwrap();
 //! wasi.spawn();
 //! let mut out = String::new();
@@ -26,23 +24,143 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! # Locking
+//!
+//! Each pipe is kept behind a lock, which is `parking_lot`'s by default. Without the default
+//! `parking_lot` feature, it's a `std::sync::Mutex` instead, e.g. to leave out the dependency;
+//! nothing else changes. Either way, the ends of a pipe can be used from different threads.
+//!
+//! ```
+//! # #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//! use wasi_process2::LockPipe;
+//! let (reader, writer) = LockPipe::pair(64)?;
+//! let write = tokio::spawn(async move {
+//!     for _ in 0..1000 {
+//!         (&writer).write_all(b"abcd").await?;
+//!     }
+//!     // dropping the write end closes it
+//!     Ok::<_, std::io::Error>(())
+//! });
+//! let read = tokio::spawn(async move {
+//!     let mut out = Vec::new();
+//!     (&reader).read_to_end(&mut out).await.map(|_| out)
+//! });
+//! write.await??;
+//! assert_eq!(read.await??, b"abcd".repeat(1000));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # Tracing
+//!
+//! With the `tracing` feature, each process runs in a `wasi_process` span, with its
+//! [name](WasiProcess::name) if it has one, and emits events when it starts and finishes,
+//! when it fails with a [`SpawnError`], when a pipe is closed, and every MiB that goes through
+//! a pipe.
+//!
+#![cfg_attr(feature = "tracing", doc = "```")]
+#![cfg_attr(not(feature = "tracing"), doc = "```ignore")]
+//! # #[tokio::main] #[tracing_test::traced_test] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use wasi_process2::WasiProcessBuilder;
+//! # let store = wasmer::Store::default();
+//! # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+//! let wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+//! wasi.await?;
+//! assert!(logs_contain("process started"));
+//! assert!(logs_contain("process finished"));
+//! assert!(logs_contain("program=\"progg\""));
+//! # Ok(())
+//! # }
+//! ```
 #![deny(missing_docs)]
 
+use std::collections::HashMap;
 use std::fmt;
 use std::future::Future;
+use std::num::NonZeroU64;
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
 use tokio::{io, task};
-use wasmer::{RuntimeError, AsStoreMut};
-use wasmer_wasi::WasiStateBuilder;
+use wasmer::{AsStoreMut, AsStoreRef, RuntimeError};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_vm::TrapCode;
+use wasmer_wasi::types::wasi::{Fdflags, Rights};
+use wasmer_wasi::{Fd, WasiError, WasiStateBuilder};
 
+mod blocking;
+mod broadcast;
+mod builder;
+mod converse;
+mod deterministic;
+mod framed;
+mod interact;
+mod lines;
+mod local;
+mod memfs;
+mod memory;
 mod pipe;
+mod pipeline;
 mod stdio;
+mod tee;
+mod throttle;
+mod turns;
+
+pub use blocking::{BlockingStdin, BlockingStdout};
+pub use broadcast::{StdoutBroadcast, StdoutSubscription};
+pub use builder::{BuildError, ProcessConfig, WasiProcessBuilder, WasiProcessTemplate};
+pub use converse::Conversation;
+pub use framed::{Endian, FramedStdin, FramedStdout};
+pub use interact::interact;
+pub use lines::{StdoutByteLines, StdoutLines};
+pub use local::LocalWasiProcess;
+pub use memfs::MemFs;
+pub use pipe::{LockPipe, PipeMetrics, PipeSnapshot};
+pub use pipeline::{pipeline, Pipeline};
+pub use stdio::{NullPipe, Stderr, Stdin, Stdout};
+pub use tee::TeeReader;
+pub use throttle::ThrottledStdin;
 
-pub use stdio::{Stderr, Stdin, Stdout};
+use memory::MemoryLimit;
+use pipe::{End, Mutex, OutputLimit};
+use turns::Turns;
 
-use pipe::LockPipe;
+/// Create a store that compiles modules with the compiler picked by this crate's features:
+/// Cranelift with `cranelift`, or Singlepass with `singlepass`. If both are enabled, Cranelift
+/// is used. Nothing else in the crate depends on the compiler, so a store made any other way
+/// works just as well.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::AsyncReadExt;
+/// use wasi_process2::WasiProcessBuilder;
+/// let store = wasi_process2::store();
+/// let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn().await?;
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "Hello, World!\n");
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(any(feature = "cranelift", feature = "singlepass"))]
+pub fn store() -> wasmer::Store {
+    #[cfg(feature = "cranelift")]
+    let compiler = wasmer::Cranelift::default();
+    #[cfg(not(feature = "cranelift"))]
+    let compiler = wasmer::Singlepass::default();
+    wasmer::Store::new(wasmer::EngineBuilder::new(compiler))
+}
 
 /// Use the wasi-process stdio pseudo-files for a wasi environment.
 ///
@@ -51,36 +169,964 @@ use pipe::LockPipe;
 /// # fn main() -> Result<(), wasmer_wasi::WasiStateCreationError> {
 /// use wasmer_wasi::WasiState;
 /// let mut state = WasiState::new("programname");
-/// wasi_process::add_stdio(&mut state);
+/// wasi_process2::add_stdio(&mut state);
 /// let state = state.arg("foo").build()?;
 /// # let _ = state;
 /// # Ok(())
 /// # }
 /// ```
 pub fn add_stdio(state: &mut WasiStateBuilder) -> &mut WasiStateBuilder {
+    add_stdio_with(state, Stdio::Piped, Stdio::Piped, Stdio::Piped)
+}
+
+/// How a stdio stream of the module is set up, for [`add_stdio_with`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Stdio {
+    /// The stream is connected to the process's [`WasiStdin`], [`WasiStdout`] or [`WasiStderr`]
+    Piped,
+    /// Everything the module writes to the stream is discarded, and reading it gets EOF
+    Null,
+    /// Only meaningful for stderr: the module's writes go into the same pipe as stdout, so they
+    /// come out of [`WasiStdout`] in exactly the order they were made. For stdin or stdout it's
+    /// the same as `Piped`.
+    ToStdout,
+    /// The stream is connected to the host's own stdio by a task that copies between them, so
+    /// the process's handle for it is `None`. Only a [`WasiProcessBuilder`] sets up that task;
+    /// for [`add_stdio_with`] it's the same as `Piped`.
+    Inherit,
+}
+
+impl Stdio {
+    /// Connect the stream to the process, see [`Stdio::Piped`].
+    pub fn piped() -> Self {
+        Self::Piped
+    }
+
+    /// Discard the stream, see [`Stdio::Null`].
+    pub fn null() -> Self {
+        Self::Null
+    }
+
+    /// Connect the stream to the host's stdio, see [`Stdio::Inherit`].
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{Stdio, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// // prints "Hello, World!" to the terminal
+    /// let wasi = WasiProcessBuilder::new("progg", module)
+    ///     .stdout(Stdio::inherit())
+    ///     .build(store)?;
+    /// assert!(wasi.stdout.is_none());
+    /// wasi.spawn().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn inherit() -> Self {
+        Self::Inherit
+    }
+
+    /// Send stderr to the stdout pipe, see [`Stdio::ToStdout`].
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{Stdio, WasiProcessBuilder};
+    /// # let mut store = wasmer::Store::default();
+    /// // writes "a" to stdout, "b" to stderr, "c" to stdout, then "d" to stderr
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) "abcd")
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 4) (i32.const 1))
+    ///         (loop $write
+    ///             (i32.store (i32.const 0) (i32.add (i32.const 16) (local.get $i)))
+    ///             (drop (call $fd_write
+    ///                 (i32.add (i32.const 1) (i32.rem_u (local.get $i) (i32.const 2)))
+    ///                 (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 4))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("both", module)
+    ///     .stderr(Stdio::to_stdout())
+    ///     .build(store)?;
+    /// assert!(wasi.stderr.is_none());
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "abcd");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_stdout() -> Self {
+        Self::ToStdout
+    }
+}
+
+/// Like [`add_stdio`], but each stream can be set to [`Stdio::Null`] instead, e.g. so that a
+/// module writing a lot to stderr doesn't stall when nobody reads it. The process's handle for a
+/// null stream just gets EOF once the process exits, as does its `stderr` if stderr is set to
+/// [`Stdio::ToStdout`].
+///
+/// # Examples
+/// ```
+/// # fn main() -> Result<(), wasmer_wasi::WasiStateCreationError> {
+/// use wasi_process2::Stdio;
+/// use wasmer_wasi::WasiState;
+/// let mut state = WasiState::new("programname");
+/// wasi_process2::add_stdio_with(&mut state, Stdio::piped(), Stdio::piped(), Stdio::null());
+/// # let _ = state.build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn add_stdio_with(
+    state: &mut WasiStateBuilder,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+) -> &mut WasiStateBuilder {
+    fn file(
+        stdio: Stdio,
+        piped: impl wasmer_wasi::WasiFile + Send + Sync + 'static,
+    ) -> Box<dyn wasmer_wasi::WasiFile + Send + Sync + 'static> {
+        match stdio {
+            Stdio::Piped | Stdio::ToStdout | Stdio::Inherit => Box::new(piped),
+            Stdio::Null => Box::new(NullPipe),
+        }
+    }
+    // writing to the stdout pseudo-file goes to the stdout pipe, whichever fd it's under
+    let stderr = match stderr {
+        Stdio::ToStdout => file(stdout, stdio::Stdout),
+        stderr => file(stderr, stdio::Stderr),
+    };
+    state
+        .stdin(file(stdin, stdio::Stdin))
+        .stdout(file(stdout, stdio::Stdout))
+        .stderr(stderr)
+}
+
+/// Like [`add_stdio`], but each stream that's given a pipe uses it directly instead of the
+/// process's own pipe, so that one process's output can be another one's input: give the write
+/// end of a [`LockPipe::pair`] to the first one as its stdout, and the read end to the second
+/// one as its stdin. The process's own handle for a stream that's been given a pipe isn't
+/// connected to anything.
+///
+/// Once the module has used a pipe, the process takes care of its end of it like it does its
+/// own pipes: it's closed once `_start` returns, so the other end gets EOF or a broken pipe
+/// error even while the process's [`SpawnHandle`] is still around, and it's closed when the
+/// process is [killed](SpawnHandle::kill) or [times out](WasiProcess::timeout), so a module
+/// that's waiting on it can finish. The module also waits at it while the process is
+/// [paused](SpawnHandle::pause). The process can't find out about a pipe the module never uses,
+/// so that one's only closed once the store is dropped.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::AsyncReadExt;
+/// use wasi_process2::{LockPipe, MaxBufSize, WasiProcess};
+/// use wasmer_wasi::WasiState;
+/// let (reader, writer) = LockPipe::pair(1024)?;
+///
+/// // writes "Hello, World!\n" into the pipe
+/// # let mut store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let mut state = WasiState::new("hello");
+/// wasi_process2::add_stdio_with_pipes(&mut state, None, Some(writer), None);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let hello = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+///
+/// // copies the pipe to its stdout until EOF, then writes "END\n"
+/// # let mut store = wasmer::Store::new(engine);
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut state = WasiState::new("echo");
+/// wasi_process2::add_stdio_with_pipes(&mut state, Some(reader), None, None);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let mut echo = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+/// let mut stdout = echo.stdout.take().unwrap();
+/// let mut out = String::new();
+/// let (hello, echo, read) = tokio::join!(
+///     hello.spawn(),
+///     echo.spawn(),
+///     stdout.read_to_string(&mut out)
+/// );
+/// hello?;
+/// echo?;
+/// read?;
+/// assert_eq!(out, "Hello, World!\nEND\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Killing a process that's waiting on a pipe closes it:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use tokio::io::AsyncWriteExt;
+/// use wasi_process2::{LockPipe, MaxBufSize, SpawnError, WasiProcess};
+/// use wasmer_wasi::WasiState;
+/// let (reader, writer) = LockPipe::pair(1024)?;
+/// // copies the pipe to its stdout until EOF, then writes "END\n"
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut state = WasiState::new("echo");
+/// wasi_process2::add_stdio_with_pipes(&mut state, Some(reader), None, None);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let echo = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+/// let handle = echo.spawn();
+/// (&writer).write_all(b"hi\n").await?;
+/// // give the module time to read that and wait for more
+/// tokio::time::sleep(Duration::from_millis(100)).await;
+/// handle.kill();
+/// assert!(matches!(handle.await, Err(SpawnError::Killed)));
+/// assert!((&writer).write_all(b"more\n").await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Pausing it holds it up at a pipe too:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use tokio::time::timeout;
+/// use wasi_process2::{LockPipe, MaxBufSize, WasiProcess};
+/// use wasmer_wasi::WasiState;
+/// let (reader, writer) = LockPipe::pair(1024)?;
+/// let (out_reader, out_writer) = LockPipe::pair(1024)?;
+/// // copies one pipe to the other until EOF, then writes "END\n"
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut state = WasiState::new("echo");
+/// wasi_process2::add_stdio_with_pipes(&mut state, Some(reader), Some(out_writer), None);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let echo = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+/// let handle = echo.spawn();
+/// let mut buf = [0; 16];
+/// (&writer).write_all(b"a").await?;
+/// assert_eq!((&out_reader).read(&mut buf).await?, 1);
+///
+/// assert!(handle.pause());
+/// // the read the module was already waiting on goes through, but not the write after it
+/// (&writer).write_all(b"b").await?;
+/// let wait = Duration::from_millis(100);
+/// assert!(timeout(wait, (&out_reader).read(&mut buf)).await.is_err());
+/// handle.resume();
+/// assert_eq!((&out_reader).read(&mut buf).await?, 1);
+/// drop(writer);
+/// handle.await?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn add_stdio_with_pipes(
+    state: &mut WasiStateBuilder,
+    stdin: Option<LockPipe>,
+    stdout: Option<LockPipe>,
+    stderr: Option<LockPipe>,
+) -> &mut WasiStateBuilder {
+    fn file(
+        pipe: Option<LockPipe>,
+        direction: Direction,
+        piped: impl wasmer_wasi::WasiFile + Send + Sync + 'static,
+    ) -> Box<dyn wasmer_wasi::WasiFile + Send + Sync + 'static> {
+        match pipe {
+            Some(pipe) => Box::new(stdio::SharedPipe { pipe, direction }),
+            None => Box::new(piped),
+        }
+    }
     state
-        .stdin(Box::new(stdio::Stdin))
-        .stdout(Box::new(stdio::Stdout))
-        .stderr(Box::new(stdio::Stderr))
+        .stdin(file(stdin, Direction::Read, stdio::Stdin))
+        .stdout(file(stdout, Direction::Write, stdio::Stdout))
+        .stderr(file(stderr, Direction::Write, stdio::Stderr))
+}
+
+/// The direction of an extra pipe, from the point of view of the module.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// The module reads from the pipe, and the host writes to it with a [`WasiStdin`]
+    Read,
+    /// The module writes to the pipe, and the host reads from it with a [`WasiStdout`]
+    Write,
+}
+
+/// Use wasi-process pipes for extra file descriptors (fd 3 and up) in a wasi environment. The
+/// host ends of the pipes are created with [`WasiProcess::pipe_writer`] and
+/// [`WasiProcess::pipe_reader`]; until then, the module gets a broken pipe error when it uses the
+/// fd.
+///
+/// Like `dup2`, whatever was at the fd before is replaced. In particular, wasmer puts its virtual
+/// root directory at fd 3. This uses [`WasiStateBuilder::setup_fs`], so it can't be combined with
+/// another `setup_fs` callback.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use tokio::io::AsyncReadExt;
+/// use wasi_process2::{Direction, MaxBufSize, WasiProcess};
+/// use wasmer_wasi::WasiState;
+/// let mut store = wasmer::Store::default();
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (data (i32.const 12) "side channel\n")
+///     (func (export "_start")
+///         (i32.store (i32.const 0) (i32.const 12))
+///         (i32.store (i32.const 4) (i32.const 13))
+///         (drop (call $fd_write (i32.const 3) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut state = WasiState::new("progg");
+/// wasi_process2::add_stdio(&mut state);
+/// wasi_process2::add_pipes(&mut state, &[(3, Direction::Write)]);
+/// let mut env = state.finalize(&mut store)?;
+/// let imports = env.import_object(&mut store, &module)?;
+/// let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// env.initialize(&mut store, &instance)?;
+/// let mut wasi = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+/// let mut fd3 = wasi.pipe_reader(3, 1024);
+/// wasi.spawn();
+/// let mut out = String::new();
+/// fd3.read_to_string(&mut out).await?;
+/// assert_eq!(out, "side channel\n");
+/// # Ok(())
+/// # }
+/// ```
+pub fn add_pipes<'a>(
+    state: &'a mut WasiStateBuilder,
+    pipes: &[(u32, Direction)],
+) -> &'a mut WasiStateBuilder {
+    let pipes = pipes.to_vec();
+    state.setup_fs(Box::new(move |inodes, fs| {
+        for &(fd, direction) in &pipes {
+            let (rights, open_flags) = match direction {
+                Direction::Read => (Rights::FD_READ, Fd::READ),
+                Direction::Write => (Rights::FD_WRITE, Fd::WRITE),
+            };
+            let file = Box::new(stdio::PipeFile { fd, direction });
+            let opened = fs
+                .open_file_at(
+                    inodes,
+                    wasmer_wasi::VIRTUAL_ROOT_FD,
+                    file,
+                    open_flags,
+                    format!("<pipe {}>", fd),
+                    rights | Rights::POLL_FD_READWRITE,
+                    Rights::empty(),
+                    Fdflags::empty(),
+                )
+                .map_err(|e| format!("couldn't open a pipe for fd {}: {}", fd, e))?;
+            if opened != fd {
+                let mut fd_map = fs.fd_map.write().unwrap();
+                let entry = fd_map.remove(&opened).unwrap();
+                fd_map.insert(fd, entry);
+                fs.preopen_fds
+                    .write()
+                    .unwrap()
+                    .retain(|&preopen| preopen != fd);
+                fs.next_fd
+                    .fetch_max(fd + 1, std::sync::atomic::Ordering::AcqRel);
+            }
+        }
+        Ok(())
+    }))
+}
+
+/// The pipes backing a process's file descriptors, keyed by fd.
+type Pipes = HashMap<u32, LockPipe>;
+
+/// The stdio of the module that's running, for the pseudo-files to find their pipes in.
+#[derive(Debug)]
+struct ModuleStdio {
+    /// The process's own pipes, see [`WasiProcess::module_stdio`].
+    pipes: Pipes,
+    /// The pipes the module was given with [`add_stdio_with_pipes`] instead.
+    shared: SharedPipes,
+    /// The turns it takes with its process, if it yields, see
+    /// [`WasiProcessBuilder::yield_interval`].
+    turns: Option<Turns>,
 }
 
 tokio::task_local! {
-    static STDIN: LockPipe;
-    static STDOUT: LockPipe;
-    static STDERR: LockPipe;
+    static STDIO: ModuleStdio;
 }
 
 /// An AsyncWrite type representing a wasi stdin stream.
+///
+/// Shutting it down (e.g. with `AsyncWriteExt::shutdown`) closes the stream without having to
+/// drop it; the module reads whatever was written before that, and then EOF. Writing after a
+/// shutdown returns a `BrokenPipe` error.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasi_process2::WasiProcessBuilder;
+/// # let mut store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn();
+/// stdin.write_all(b"hello").await?;
+/// stdin.shutdown().await?;
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "helloEND\n");
+/// assert!(stdin.write_all(b"more").await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Flushing wakes up the module if there's written data it hasn't read yet, so an interactive
+/// module can answer without waiting for the next write:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasi_process2::WasiProcessBuilder;
+/// # let mut store = wasmer::Store::default();
+/// // answers every read from stdin with "pong\n", until EOF
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (data (i32.const 16) "pong\n")
+///     (func (export "_start")
+///         (loop $answer
+///             (i32.store (i32.const 0) (i32.const 64))
+///             (i32.store (i32.const 4) (i32.const 1024))
+///             (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+///             (if (i32.load (i32.const 8))
+///                 (then
+///                     (i32.store (i32.const 0) (i32.const 16))
+///                     (i32.store (i32.const 4) (i32.const 5))
+///                     (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+///                     (br $answer))))))"#;
+/// # let module = wasmer::Module::new(&store, wat)?;
+/// # let mut wasi = WasiProcessBuilder::new("pingpong", module).build(store)?;
+/// # let mut stdin = wasi.stdin.take().unwrap();
+/// # let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn();
+/// for _ in 0..3 {
+///     stdin.write_all(b"ping\n").await?;
+///     stdin.flush().await?;
+///     let mut reply = [0; 5];
+///     stdout.read_exact(&mut reply).await?;
+///     assert_eq!(&reply, b"pong\n");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Vectored writes copy as many of the slices as fit in the buffer while only locking the pipe
+/// once:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::io::IoSlice;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut wasi = WasiProcessBuilder::new("echo", module)
+///     .max_buf_size(MaxBufSize { stdin: 8, ..MaxBufSize::default() })
+///     .build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// let bufs = [IoSlice::new(b"hello "), IoSlice::new(b"vectored "), IoSlice::new(b"world")];
+/// // the module isn't running yet, so only the first 8 bytes fit
+/// assert_eq!(stdin.write_vectored(&bufs).await?, 8);
+/// wasi.spawn();
+/// stdin.write_all(b"ctored world").await?;
+/// stdin.shutdown().await?;
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "hello vectored worldEND\n");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Dropping the `WasiStdin` closes the write end, so the module reads whatever's left and then
+/// EOF. Once the module's end is gone, because the process finished without reading all of
+/// stdin, writes fail with `BrokenPipe` instead:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasi_process2::WasiProcessBuilder;
+/// # let mut store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// stdin.write_all(b"bye").await?;
+/// drop(stdin);
+/// wasi.spawn().await?;
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "byeEND\n");
+///
+/// # let store = wasmer::Store::new(engine);
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let mut wasi = WasiProcessBuilder::new("hello", module).build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// wasi.spawn().await?;
+/// let err = stdin.write_all(b"nobody's listening").await.unwrap_err();
+/// assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// That goes for a write that's already waiting for room, too: it's woken and fails as soon as
+/// the module's end goes away, rather than waiting for a reader that won't come back:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use tokio::io::AsyncWriteExt;
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// // reads 16 bytes of stdin, and exits without reading any more
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start")
+///         (i32.store (i32.const 0) (i32.const 64))
+///         (i32.store (i32.const 4) (i32.const 16))
+///         (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut wasi = WasiProcessBuilder::new("head", module)
+///     .max_buf_size(MaxBufSize { stdin: 64, ..MaxBufSize::default() })
+///     .build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let handle = wasi.spawn();
+/// let input = vec![b'x'; 1 << 20];
+/// let write = tokio::time::timeout(Duration::from_secs(10), stdin.write_all(&input)).await;
+/// let err = write.expect("the write shouldn't hang").unwrap_err();
+/// assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+/// handle.await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Closing stdin only closes stdin, so a filter that reads everything before it writes anything
+/// can still write all of its output after it's seen EOF:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// // reads all of stdin into memory, then writes it to stdout in one go
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (global $len (mut i32) (i32.const 0))
+///     (func (export "_start")
+///         (loop $read
+///             (i32.store (i32.const 0) (i32.add (i32.const 64) (global.get $len)))
+///             (i32.store (i32.const 4) (i32.const 1024))
+///             (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+///             (global.set $len (i32.add (global.get $len) (i32.load (i32.const 8))))
+///             (br_if $read (i32.load (i32.const 8))))
+///         (i32.store (i32.const 0) (i32.const 64))
+///         (i32.store (i32.const 4) (global.get $len))
+///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut wasi = WasiProcessBuilder::new("filter", module)
+///     .max_buf_size(MaxBufSize { stdin: 64, stdout: 64, stderr: 64 })
+///     .build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// let handle = wasi.spawn();
+/// let input: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+/// stdin.write_all(&input).await?;
+/// drop(stdin);
+/// let mut out = Vec::new();
+/// stdout.read_to_end(&mut out).await?;
+/// assert_eq!(out, input);
+/// handle.await?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Like with any `AsyncWrite`, a single write only takes as much as there's room for in the
+/// stdin buffer, and returns how many bytes that was, so a `write` or `poll_write` that isn't
+/// checked can lose data. Use `write_all`, or [`write_all_and_close`](Self::write_all_and_close)
+/// or [`write_bytes`](Self::write_bytes), which keep writing until everything's gone in:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::future::poll_fn;
+/// use std::pin::Pin;
+/// use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut wasi = WasiProcessBuilder::new("echo", module)
+///     .max_buf_size(MaxBufSize { stdin: 1024, ..MaxBufSize::default() })
+///     .build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// let input: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+/// let written = poll_fn(|cx| Pin::new(&mut stdin).poll_write(cx, &input)).await?;
+/// // a short write, since only 1 KiB fits
+/// assert_eq!(written, 1024);
+/// let handle = wasi.spawn();
+/// let write = async {
+///     stdin.write_all(&input[written..]).await?;
+///     stdin.shutdown().await
+/// };
+/// let mut out = Vec::new();
+/// let (written, read) = tokio::join!(write, stdout.read_to_end(&mut out));
+/// written?;
+/// read?;
+/// handle.await?;
+/// assert_eq!(out, [&input[..], b"END\n"].concat());
+/// # Ok(())
+/// # }
+/// ```
 pub struct WasiStdin {
     inner: LockPipe,
 }
 
+impl WasiStdin {
+    /// Get a snapshot of the counters of the stdin pipe.
+    pub fn metrics(&self) -> PipeMetrics {
+        self.inner.metrics()
+    }
+
+    /// Get how many bytes the module has read from stdin so far. Unlike a completed write, which
+    /// only means the bytes made it into the stdin buffer, this counts what the module has
+    /// actually taken out of it, so the bytes still in flight are
+    /// `metrics().bytes_written - flushed_to_reader()`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncWriteExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // reads 5 bytes from stdin once, and exits
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 64))
+    ///         (i32.store (i32.const 4) (i32.const 5))
+    ///         (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("half", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// stdin.write_all(b"0123456789").await?;
+    /// wasi.spawn().await?;
+    /// assert_eq!(stdin.flushed_to_reader(), 5);
+    /// assert_eq!(stdin.metrics().bytes_written - stdin.flushed_to_reader(), 5);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn flushed_to_reader(&self) -> u64 {
+        self.inner.metrics().bytes_read
+    }
+
+    /// Write to stdin one frame at a time with [`FramedStdin::send`], each one prefixed with its
+    /// length as a `u32` in the given byte order, for modules that read a length-prefixed
+    /// protocol. See [`WasiStdout::framed`] for reading frames back.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncWriteExt;
+    /// use wasi_process2::{Endian, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, so the frames come back unchanged
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (loop $copy
+    ///             (i32.store (i32.const 0) (i32.const 64))
+    ///             (i32.store (i32.const 4) (i32.const 1024))
+    ///             (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (if (i32.load (i32.const 8))
+    ///                 (then
+    ///                     (i32.store (i32.const 4) (i32.load (i32.const 8)))
+    ///                     (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///                     (br $copy))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("cat", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap().framed(Endian::Little);
+    /// let mut stdout = wasi.stdout.take().unwrap().framed(Endian::Little);
+    /// wasi.spawn();
+    /// stdin.send(b"hello").await?;
+    /// stdin.send(b"").await?;
+    /// stdin.send(&[7; 1000]).await?;
+    /// stdin.into_inner().shutdown().await?;
+    /// assert_eq!(stdout.next_frame().await?.unwrap(), &b"hello"[..]);
+    /// assert_eq!(stdout.next_frame().await?.unwrap(), &b""[..]);
+    /// assert_eq!(stdout.next_frame().await?.unwrap(), &[7; 1000][..]);
+    /// assert_eq!(stdout.next_frame().await?, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn framed(self, endian: Endian) -> FramedStdin {
+        FramedStdin::new(self, endian)
+    }
+
+    /// Let bytes through to the module no faster than `bytes_per_sec`, to see how it copes with
+    /// input that trickles in, e.g. over a slow network. Each write waits its turn and then
+    /// writes at most a hundredth of a second's worth, so writing `n` bytes takes at least
+    /// `n / bytes_per_sec` seconds. Time spent not writing doesn't save up for a burst later.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::num::NonZeroU64;
+    /// use std::time::{Duration, Instant};
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let rate = NonZeroU64::new(20_000).unwrap();
+    /// let mut stdin = wasi.stdin.take().unwrap().throttle(rate);
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let start = Instant::now();
+    /// let write = async {
+    ///     stdin.write_all(&[b'a'; 2000]).await?;
+    ///     stdin.shutdown().await
+    /// };
+    /// let mut out = Vec::new();
+    /// let (write, read) = tokio::join!(write, stdout.read_to_end(&mut out));
+    /// write?;
+    /// read?;
+    /// // 2000 bytes at 20,000 bytes/sec
+    /// assert!(start.elapsed() >= Duration::from_millis(100));
+    /// assert_eq!(out, [&[b'a'; 2000][..], b"END\n"].concat());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn throttle(self, bytes_per_sec: NonZeroU64) -> ThrottledStdin {
+        ThrottledStdin::new(self, bytes_per_sec)
+    }
+
+    /// Turn stdin into a `std::io::Write` for a blocking thread, like
+    /// [`into_blocking_write_with`](Self::into_blocking_write_with) with the handle of the
+    /// current runtime. This panics if it isn't called from within a tokio runtime, so call it
+    /// before moving stdin to the blocking thread.
+    pub fn into_blocking_write(self) -> BlockingStdin {
+        self.into_blocking_write_with(tokio::runtime::Handle::current())
+    }
+
+    /// Turn stdin into a `std::io::Write` for a blocking thread, that waits for room in the
+    /// buffer by blocking on `handle`. See [`WasiStdout::into_blocking_read_with`] for where it
+    /// can be used, and for an example.
+    pub fn into_blocking_write_with(self, handle: tokio::runtime::Handle) -> BlockingStdin {
+        BlockingStdin::new(self, handle)
+    }
+
+    /// Write all of `data` to stdin, waiting for the module to make room as needed, and then
+    /// shut it down, so the module reads EOF after it. `data` can be bigger than the stdin
+    /// buffer, but then something has to be reading the module's output at the same time if it
+    /// writes any, or they'll both end up waiting on each other.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .max_buf_size(MaxBufSize { stdin: 1024, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let input = vec![b'x'; 10 * 1024];
+    /// let mut out = Vec::new();
+    /// let (written, read) = tokio::join!(
+    ///     stdin.write_all_and_close(&input),
+    ///     stdout.read_to_end(&mut out),
+    /// );
+    /// written?;
+    /// read?;
+    /// handle.await?;
+    /// assert_eq!(out.len(), input.len() + 4);
+    /// assert!(out.ends_with(b"xxEND\n"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn write_all_and_close(mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data).await?;
+        self.shutdown().await
+    }
+
+    /// Try to write to stdin without waiting. Returns the amount of bytes written, which may be
+    /// less than `buf.len()`, or a `WouldBlock` error if the buffer is full.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use std::io::ErrorKind;
+    /// # use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module)
+    ///     .max_buf_size(MaxBufSize { stdin: 4, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let stdin = wasi.stdin.take().unwrap();
+    /// assert_eq!(stdin.try_write(b"hello")?, 4);
+    /// assert_eq!(stdin.try_write(b"o").unwrap_err().kind(), ErrorKind::WouldBlock);
+    /// // the module never reads stdin, and it's closed once the module exits
+    /// wasi.spawn().await?;
+    /// assert_eq!(stdin.try_write(b"o").unwrap_err().kind(), ErrorKind::BrokenPipe);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.try_write(buf)
+    }
+
+    /// Write all of `data` to stdin, waiting for the module to make room as needed, like
+    /// `write_all`. When stdin's buffer is empty and `data` fits in it whole, `data` is handed to
+    /// the pipe as it is instead of being copied into the buffer, as long as nothing else holds a
+    /// reference to its memory, e.g. a `Bytes` made from a `Vec`. Otherwise it's copied like any
+    /// other write, so this is never worse than `write_all`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use bytes::Bytes;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .max_buf_size(MaxBufSize { stdin: 16, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// // nothing's in the buffer yet, so this is taken over without a copy
+    /// stdin.write_bytes(Bytes::from(b"hello ".to_vec())).await?;
+    /// assert_eq!(stdin.metrics().allocations, 0);
+    /// let handle = wasi.spawn();
+    /// let write = async {
+    ///     stdin.write_bytes(Bytes::from_static(b"wasi, ")).await?;
+    ///     // bigger than the buffer, so it goes in over several writes
+    ///     stdin.write_bytes(Bytes::from(vec![b'!'; 100])).await?;
+    ///     stdin.write_bytes(Bytes::new()).await?;
+    ///     stdin.write_bytes(Bytes::from_static(b" bye")).await?;
+    ///     stdin.shutdown().await
+    /// };
+    /// let mut out = Vec::new();
+    /// let (written, read) = tokio::join!(write, stdout.read_to_end(&mut out));
+    /// written?;
+    /// read?;
+    /// handle.await?;
+    /// let expected = [&b"hello wasi, "[..], &[b'!'; 100], b" byeEND\n"].concat();
+    /// assert_eq!(out, expected);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_bytes(&self, data: bytes::Bytes) -> impl Future<Output = io::Result<()>> + '_ {
+        let mut data = data;
+        std::future::poll_fn(move |cx| {
+            while !data.is_empty() {
+                ready!(self.inner.poll_write_bytes(cx, &mut data))?;
+            }
+            Poll::Ready(Ok(()))
+        })
+    }
+
+    /// Poll for room in the stdin buffer, returning how many bytes can be written without
+    /// waiting. If the buffer is full, the current task is woken once the module reads from it.
+    /// Once stdin is closed this is always ready, and the next write returns the error.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::future::poll_fn;
+    /// use std::task::Poll;
+    /// # use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .max_buf_size(MaxBufSize { stdin: 4, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let stdin = wasi.stdin.take().unwrap();
+    /// assert_eq!(poll_fn(|cx| stdin.poll_capacity(cx)).await, 4);
+    /// stdin.try_write(b"ping")?;
+    /// assert!(poll_fn(|cx| Poll::Ready(stdin.poll_capacity(cx).is_pending())).await);
+    /// wasi.spawn();
+    /// // the module reads everything in the buffer
+    /// assert_eq!(poll_fn(|cx| stdin.poll_capacity(cx)).await, 4);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn poll_capacity(&self, cx: &mut Context) -> Poll<usize> {
+        self.inner.poll_capacity(cx)
+    }
+
+    /// Change the size of the stdin buffer. See [`WasiStdout::set_max_buf_size`].
+    pub fn set_max_buf_size(&self, max_buf_size: usize) -> Result<(), NewError> {
+        check_buf_size("stdin", max_buf_size)?;
+        self.inner.set_max_buf_size(max_buf_size);
+        Ok(())
+    }
+
+    /// Save the data the module hasn't read yet, see [`PipeSnapshot`].
+    pub fn snapshot(&self) -> PipeSnapshot {
+        self.inner.snapshot()
+    }
+
+    /// Replace whatever is buffered in stdin with a snapshot, e.g. one taken from another
+    /// process. If the snapshot was taken after stdin was closed, this closes it too; stdin
+    /// that's already closed stays closed either way. Fails with [`NewError::ZeroBufSize`] if
+    /// the snapshot's `max_buf_size` is 0, e.g. from a corrupted one that's been deserialized.
+    pub fn restore(&self, snapshot: &PipeSnapshot) -> Result<(), NewError> {
+        check_buf_size("stdin", snapshot.max_buf_size)?;
+        self.inner.restore(snapshot);
+        Ok(())
+    }
+}
+
 impl AsyncWrite for WasiStdin {
     #[inline]
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
         Pin::new(&mut &self.inner).poll_write(cx, buf)
     }
     #[inline]
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        bufs: &[std::io::IoSlice],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut &self.inner).poll_write_vectored(cx, bufs)
+    }
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+    #[inline]
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
         Pin::new(&mut &self.inner).poll_flush(cx)
     }
@@ -91,8 +1137,644 @@ impl AsyncWrite for WasiStdin {
 }
 
 /// An AsyncRead type representing a wasi stdout stream.
+///
+/// Once the module is done, stdout is closed, but whatever's still in the buffer stays there
+/// for the reader, which only gets EOF after it's read all of it. If it's dropped with output
+/// still in the buffer, that output is lost. With the `tracing` feature that's logged as a
+/// warning, or [`on_unread_drop`](Self::on_unread_drop) can be used to find out about it.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::time::Duration;
+/// use tokio::io::AsyncReadExt;
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// // writes 4000 bytes to stdout in one go, and returns right away
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start")
+///         (memory.fill (i32.const 64) (i32.const 120) (i32.const 4000))
+///         (i32.store (i32.const 0) (i32.const 64))
+///         (i32.store (i32.const 4) (i32.const 4000))
+///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut wasi = WasiProcessBuilder::new("burst", module)
+///     .max_buf_size(MaxBufSize { stdout: 4096, ..MaxBufSize::default() })
+///     .build(store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn().await?;
+/// // a slow reader that only gets going after the module's gone
+/// tokio::time::sleep(Duration::from_millis(10)).await;
+/// let mut out = Vec::new();
+/// let mut chunk = [0; 100];
+/// loop {
+///     match stdout.read(&mut chunk).await? {
+///         0 => break,
+///         n => out.extend_from_slice(&chunk[..n]),
+///     }
+/// }
+/// assert_eq!(out, [b'x'; 4000]);
+/// # Ok(())
+/// # }
+/// ```
 pub struct WasiStdout {
     inner: LockPipe,
+    /// Called with the amount of unread bytes if there are any when this is dropped.
+    on_unread_drop: Option<Box<dyn FnOnce(usize) + Send + Sync>>,
+}
+impl WasiStdout {
+    fn new(inner: LockPipe) -> Self {
+        Self {
+            inner,
+            on_unread_drop: None,
+        }
+    }
+
+    /// Call `f` with how many bytes were left in the buffer if this is dropped before all of
+    /// the output has been read, to catch a consumer that forgot to drain stdout. `f` is called
+    /// instead of logging the warning the `tracing` feature would otherwise log. Output that the
+    /// module tries to write after this is dropped isn't counted, since it gets an error instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let lost = Arc::new(AtomicUsize::new(0));
+    /// stdout.on_unread_drop({
+    ///     let lost = lost.clone();
+    ///     move |unread| lost.store(unread, Ordering::SeqCst)
+    /// });
+    /// wasi.spawn().await?;
+    /// drop(stdout);
+    /// assert_eq!(lost.load(Ordering::SeqCst), "Hello, World!\n".len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_unread_drop(&mut self, f: impl FnOnce(usize) + Send + Sync + 'static) -> &mut Self {
+        self.on_unread_drop = Some(Box::new(f));
+        self
+    }
+
+    /// Get a snapshot of the counters of the stdout pipe.
+    pub fn metrics(&self) -> PipeMetrics {
+        self.inner.metrics()
+    }
+
+    /// Try to read from stdout without waiting. Returns the amount of bytes read, `Ok(0)` if the
+    /// stream is closed and everything has been read, or a `WouldBlock` error if there's nothing
+    /// to read yet.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use std::io::ErrorKind;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// let mut buf = [0; 64];
+    /// assert_eq!(stdout.try_read(&mut buf).unwrap_err().kind(), ErrorKind::WouldBlock);
+    /// wasi.spawn().await?;
+    /// let n = stdout.try_read(&mut buf)?;
+    /// assert_eq!(&buf[..n], b"Hello, World!\n");
+    /// assert_eq!(stdout.try_read(&mut buf)?, 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.try_read(buf)
+    }
+
+    /// Read from stdout like `AsyncReadExt::read`, but tell EOF apart from reading nothing into
+    /// an empty `buf`, which both come back as `Ok(0)` from a plain read. With an empty `buf`,
+    /// this doesn't wait: it's [`ReadStatus::Eof`] if stdout is closed and everything has been
+    /// read, and `Data(0)` otherwise.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{ReadStatus, WasiProcessBuilder};
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// // "Hello, World!\n" hasn't been read yet, but a plain read can't tell
+    /// assert_eq!(stdout.read(&mut []).await?, 0);
+    /// assert_eq!(stdout.read_eof_aware(&mut []).await?, ReadStatus::Data(0));
+    /// let mut buf = [0; 64];
+    /// assert_eq!(stdout.read_eof_aware(&mut buf).await?, ReadStatus::Data(14));
+    /// // the same as before for a plain read, but this time it's EOF
+    /// assert_eq!(stdout.read(&mut []).await?, 0);
+    /// assert_eq!(stdout.read_eof_aware(&mut []).await?, ReadStatus::Eof);
+    /// assert_eq!(stdout.read_eof_aware(&mut buf).await?, ReadStatus::Eof);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_eof_aware(&mut self, buf: &mut [u8]) -> io::Result<ReadStatus> {
+        if buf.is_empty() {
+            return Ok(if self.inner.is_eof() {
+                ReadStatus::Eof
+            } else {
+                ReadStatus::Data(0)
+            });
+        }
+        match self.read(buf).await? {
+            0 => Ok(ReadStatus::Eof),
+            n => Ok(ReadStatus::Data(n)),
+        }
+    }
+
+    /// Copy up to `buf.len()` of the currently buffered bytes into `buf` without consuming them,
+    /// returning how many were copied.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use tokio::io::AsyncReadExt;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// let mut header = [0; 5];
+    /// assert_eq!(stdout.peek(&mut header), 5);
+    /// assert_eq!(&header, b"Hello");
+    /// assert_eq!(stdout.peek(&mut header), 5);
+    /// assert_eq!(&header, b"Hello");
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn peek(&self, buf: &mut [u8]) -> usize {
+        self.inner.peek(buf)
+    }
+
+    /// Split stdout into two readers that both get everything the module writes. Reading from
+    /// one reader pulls the next chunk from stdout for both of them, but only as much as the
+    /// slower one has room for, so neither can fall more than the stdout buffer size behind.
+    /// Dropping one of the readers lets the other one read at its own pace.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let (mut log, mut main) = wasi.stdout.take().unwrap().tee();
+    /// wasi.spawn();
+    /// let (mut logged, mut out) = (Vec::new(), Vec::new());
+    /// let (res1, res2) = tokio::join!(log.read_to_end(&mut logged), main.read_to_end(&mut out));
+    /// res1?;
+    /// res2?;
+    /// assert_eq!(logged, b"Hello, World!\n");
+    /// assert_eq!(out, logged);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn tee(self) -> (TeeReader, TeeReader) {
+        tee::tee(self)
+    }
+
+    /// Share stdout with any number of readers that can come and go, e.g. live log viewers.
+    /// This spawns a task that reads stdout as fast as the module writes it, and each
+    /// [subscription](StdoutBroadcast::subscribe) gets what's written after it was made.
+    ///
+    /// Unlike [`tee`](Self::tee), a slow reader doesn't hold up the module: once a subscription
+    /// is `capacity` chunks behind, it skips the oldest ones. `capacity` must be at least 1.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let stdout = wasi.stdout.take().unwrap().broadcast(16);
+    /// wasi.spawn();
+    ///
+    /// let mut early = stdout.subscribe();
+    /// stdin.write_all(b"a").await?;
+    /// let mut first = [0; 1];
+    /// early.read_exact(&mut first).await?;
+    /// assert_eq!(&first, b"a");
+    ///
+    /// let mut late = stdout.subscribe();
+    /// stdin.write_all(b"b").await?;
+    /// stdin.shutdown().await?;
+    /// let (mut early_out, mut late_out) = (String::new(), String::new());
+    /// early.read_to_string(&mut early_out).await?;
+    /// late.read_to_string(&mut late_out).await?;
+    /// assert_eq!(early_out, "bEND\n");
+    /// assert_eq!(late_out, "bEND\n");
+    /// assert_eq!(early.missed(), 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn broadcast(self, capacity: usize) -> StdoutBroadcast {
+        broadcast::broadcast(self, capacity)
+    }
+
+    /// Read stdout line by line, e.g. for an interactive console. Lines are split on `\n`, with
+    /// a `\r` before it removed too, and can span any number of writes by the module. Whatever
+    /// comes after the last `\n` is returned as the last line.
+    ///
+    /// The lines can be read with [`StdoutLines::next_line`], or as a `Stream`. A line that
+    /// isn't valid UTF-8 is an `InvalidData` error; use [`byte_lines`](Self::byte_lines) to get
+    /// the raw bytes instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::io::ErrorKind;
+    /// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) "one\r\ntwo\n\ff\nthree")
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// // a tiny buffer, so the lines get split up between reads
+    /// let mut wasi = WasiProcessBuilder::new("lines", module)
+    ///     .max_buf_size(MaxBufSize { stdout: 4, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let mut lines = wasi.stdout.take().unwrap().lines();
+    /// wasi.spawn();
+    /// assert_eq!(lines.next_line().await?.as_deref(), Some("one"));
+    /// assert_eq!(lines.next_line().await?.as_deref(), Some("two"));
+    /// let err = lines.next_line().await.unwrap_err();
+    /// assert_eq!(err.kind(), ErrorKind::InvalidData);
+    /// assert_eq!(lines.next_line().await?.as_deref(), Some("three"));
+    /// assert_eq!(lines.next_line().await?, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn lines(self) -> StdoutLines {
+        StdoutLines::new(self)
+    }
+
+    /// Read stdout line by line like [`lines`](Self::lines), but as bytes, so there's no
+    /// requirement for the lines to be UTF-8.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut lines = wasi.stdout.take().unwrap().byte_lines();
+    /// wasi.spawn();
+    /// assert_eq!(lines.next_line().await, Some(b"Hello, World!".to_vec()));
+    /// assert_eq!(lines.next_line().await, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn byte_lines(self) -> StdoutByteLines {
+        StdoutByteLines::new(self)
+    }
+
+    /// Read stdout one frame at a time, each one prefixed with its length as a `u32` in the
+    /// given byte order. The frames can be read with [`FramedStdout::next_frame`], or as a
+    /// `Stream`. See [`WasiStdin::framed`] for an example.
+    pub fn framed(self, endian: Endian) -> FramedStdout {
+        FramedStdout::new(self, endian)
+    }
+
+    /// Turn stdout into a `std::io::Read` for a blocking thread, like
+    /// [`into_blocking_read_with`](Self::into_blocking_read_with) with the handle of the
+    /// current runtime. This panics if it isn't called from within a tokio runtime, so call it
+    /// before moving stdout to the blocking thread.
+    pub fn into_blocking_read(self) -> BlockingStdout {
+        self.into_blocking_read_with(tokio::runtime::Handle::current())
+    }
+
+    /// Turn stdout into a `std::io::Read` for a blocking thread, that waits for output by
+    /// blocking on `handle`, e.g. for code that drives the process from a thread of its own.
+    ///
+    /// Reading from it has to happen on a thread that isn't running async code, like one from
+    /// `tokio::task::spawn_blocking` or `std::thread::spawn`; calling it from within an async
+    /// context panics. The module has to be making progress on some other thread in the
+    /// meantime, or the read never returns: on a current-thread runtime, only the thread
+    /// that's blocked in `Runtime::block_on` runs tasks, so a spawned process is stuck for as
+    /// long as that thread is blocked on stdout instead.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::io::{Read, Write};
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap().into_blocking_write();
+    /// let handle = tokio::runtime::Handle::current();
+    /// let mut stdout = wasi.stdout.take().unwrap().into_blocking_read_with(handle);
+    /// wasi.spawn();
+    /// let out = tokio::task::spawn_blocking(move || {
+    ///     stdin.write_all(b"hello\n")?;
+    ///     drop(stdin);
+    ///     let mut out = Vec::new();
+    ///     std::io::copy(&mut stdout, &mut out)?;
+    ///     Ok::<_, std::io::Error>(out)
+    /// })
+    /// .await??;
+    /// assert_eq!(out, b"hello\nEND\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_blocking_read_with(self, handle: tokio::runtime::Handle) -> BlockingStdout {
+        BlockingStdout::new(self, handle)
+    }
+
+    /// Whether the module is done writing to stdout: it finished, or the stream was closed for
+    /// it, like when the process is dropped. There can still be data buffered to read, which
+    /// tells this apart from a read that just has nothing to return yet.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// assert!(!stdout.is_closed());
+    /// wasi.spawn().await?;
+    /// assert!(stdout.is_closed());
+    /// assert_eq!(stdout.drain_remaining(), "Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_write_closed()
+    }
+
+    /// Stop reading stdout, like closing the read end of a pipe: whatever's buffered is thrown
+    /// away, and the module's writes to stdout fail with `EPIPE` from then on, including one
+    /// that's waiting for room, rather than filling the buffer and waiting for a reader that's
+    /// never coming. Dropping stdout does the same, except that it counts the buffered output as
+    /// unread, see [`on_unread_drop`](Self::on_unread_drop).
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{MaxBufSize, TerminationReason, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // writes to stdout until a write fails, and exits with its errno
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $errno i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (loop $write
+    ///             (local.set $errno
+    ///                 (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (br_if $write (i32.eqz (local.get $errno))))
+    ///         (call $proc_exit (local.get $errno)))
+    ///     (func (param i64)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("yes", module)
+    ///     .max_buf_size(MaxBufSize { stdout: 64, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let mut first = [0; 16];
+    /// stdout.read_exact(&mut first).await?;
+    /// // the module's stuck on a full buffer until this
+    /// stdout.close();
+    /// // EPIPE
+    /// assert_eq!(handle.termination().await, TerminationReason::Exited(64));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn close(self) {
+        self.inner.discard();
+    }
+
+    /// Take everything that's currently buffered in stdout without waiting, e.g. to grab what's
+    /// left after the process has exited. Returns an empty `Bytes` if there's nothing buffered.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// assert_eq!(stdout.drain_remaining(), "Hello, World!\n");
+    /// assert!(stdout.drain_remaining().is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn drain_remaining(&self) -> bytes::Bytes {
+        self.inner.drain()
+    }
+
+    /// Wait until there's something in stdout and take all of it, without copying it out of the
+    /// pipe's buffer. This is cheaper than reading into a buffer when the output is passed on to
+    /// something that takes `Bytes` anyway. Returns an empty `Bytes` at EOF.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncWriteExt;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let payload: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+    /// let write = async {
+    ///     stdin.write_all(&payload).await?;
+    ///     stdin.shutdown().await
+    /// };
+    /// let forward = async {
+    ///     let mut chunks = Vec::new();
+    ///     loop {
+    ///         let chunk = stdout.read_bytes().await?;
+    ///         if chunk.is_empty() {
+    ///             return Ok::<_, std::io::Error>(chunks);
+    ///         }
+    ///         chunks.push(chunk);
+    ///     }
+    /// };
+    /// let (written, chunks) = tokio::join!(write, forward);
+    /// written?;
+    /// let out = chunks?.concat();
+    /// assert_eq!(out.len(), payload.len() + 4);
+    /// assert_eq!(&out[..payload.len()], &payload[..]);
+    /// assert_eq!(stdout.metrics().bytes_read, out.len() as u64);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_bytes(&self) -> impl Future<Output = io::Result<bytes::Bytes>> + '_ {
+        self.read_chunk(usize::MAX)
+    }
+
+    /// Like [`read_bytes`](Self::read_bytes), but take at most `max` bytes, e.g. to hand them on
+    /// in pieces of a bounded size. Every call takes as much as it can at once, so reading a big
+    /// output takes as many calls as it has chunks, however many bytes those are. `max` has to be
+    /// more than 0, since an empty `Bytes` means EOF.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let payload = vec![b'a'; 64 * 1024];
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .stdin_data(payload)
+    ///     .max_buf_size(MaxBufSize { stdout: 128 * 1024, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// let mut out = Vec::new();
+    /// let mut reads = 0;
+    /// loop {
+    ///     let chunk = stdout.read_chunk(16 * 1024).await?;
+    ///     reads += 1;
+    ///     if chunk.is_empty() {
+    ///         break;
+    ///     }
+    ///     assert!(chunk.len() <= 16 * 1024);
+    ///     out.extend_from_slice(&chunk);
+    /// }
+    /// assert_eq!(out.len(), 64 * 1024 + 4);
+    /// // four full chunks, "END\n", and EOF
+    /// assert_eq!(reads, 6);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn read_chunk(&self, max: usize) -> impl Future<Output = io::Result<bytes::Bytes>> + '_ {
+        std::future::poll_fn(move |cx| self.inner.poll_read_bytes(cx, max).map(Ok))
+    }
+
+    /// Read everything until EOF, like `read_to_end`, but without ever holding more than `max`
+    /// bytes, so a runaway module can't make the host run out of memory. If there's more than
+    /// `max` bytes of output, this stops reading and returns an `Other` error; whatever was read
+    /// up to then is lost.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module.clone()).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// let err = stdout.read_to_end_capped(10).await.unwrap_err();
+    /// assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    ///
+    /// // exactly the cap is fine
+    /// # let store = wasmer::Store::new(engine);
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// assert_eq!(stdout.read_to_end_capped(14).await?, b"Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_to_end_capped(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        loop {
+            // one byte more than fits, to tell hitting the cap apart from going over it
+            let left = (max - out.len()).saturating_add(1);
+            let chunk = self.read_chunk(left).await?;
+            if chunk.is_empty() {
+                return Ok(out);
+            }
+            if chunk.len() == left {
+                return Err(io::Error::other(format!(
+                    "stdout is longer than the cap of {} bytes",
+                    max
+                )));
+            }
+            out.extend_from_slice(&chunk);
+        }
+    }
+
+    /// Change the size of the stdout buffer, e.g. if the module keeps waiting for it to be read.
+    /// If this makes room, a module waiting to write continues right away. Shrinking the buffer
+    /// below what's currently in it doesn't drop anything; the module just waits until enough has
+    /// been read. Fails with [`NewError::ZeroBufSize`] if `max_buf_size` is 0, since the module
+    /// could never write anything, leaving the size as it was.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::{MaxBufSize, NewError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module)
+    ///     .max_buf_size(MaxBufSize { stdout: 4, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// let stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// // the module is stuck after writing the first 4 bytes of "Hello, World!\n"
+    /// while stdout.metrics().current_len < 4 {
+    ///     tokio::task::yield_now().await;
+    /// }
+    /// let err = stdout.set_max_buf_size(0).unwrap_err();
+    /// assert!(matches!(err, NewError::ZeroBufSize("stdout")));
+    /// stdout.set_max_buf_size(64)?;
+    /// handle.await?;
+    /// assert_eq!(stdout.metrics().current_len, 14);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_max_buf_size(&self, max_buf_size: usize) -> Result<(), NewError> {
+        check_buf_size("stdout", max_buf_size)?;
+        self.inner.set_max_buf_size(max_buf_size);
+        Ok(())
+    }
+
+    /// Save the output that hasn't been read yet, see [`PipeSnapshot`].
+    pub fn snapshot(&self) -> PipeSnapshot {
+        self.inner.snapshot()
+    }
+
+    /// Replace whatever is buffered in stdout with a snapshot. See [`WasiStdin::restore`].
+    pub fn restore(&self, snapshot: &PipeSnapshot) -> Result<(), NewError> {
+        check_buf_size("stdout", snapshot.max_buf_size)?;
+        self.inner.restore(snapshot);
+        Ok(())
+    }
 }
 impl AsyncRead for WasiStdout {
     fn poll_read(
@@ -103,11 +1785,66 @@ impl AsyncRead for WasiStdout {
         Pin::new(&mut &self.inner).poll_read(cx, buf)
     }
 }
+impl Drop for WasiStdout {
+    fn drop(&mut self) {
+        let unread = self.inner.metrics().current_len;
+        if unread == 0 {
+            return;
+        }
+        match self.on_unread_drop.take() {
+            Some(f) => f(unread),
+            #[cfg(feature = "tracing")]
+            None => tracing::warn!(unread, "stdout dropped with unread output"),
+            #[cfg(not(feature = "tracing"))]
+            None => {}
+        }
+    }
+}
 
 /// An AsyncRead type representing a wasi stderr stream.
 pub struct WasiStderr {
     inner: LockPipe,
 }
+impl WasiStderr {
+    /// Get a snapshot of the counters of the stderr pipe.
+    pub fn metrics(&self) -> PipeMetrics {
+        self.inner.metrics()
+    }
+
+    /// Whether the module is done writing to stderr. See [`WasiStdout::is_closed`].
+    pub fn is_closed(&self) -> bool {
+        self.inner.is_write_closed()
+    }
+
+    /// Stop reading stderr, so the module's writes to it fail. See [`WasiStdout::close`].
+    pub fn close(self) {
+        self.inner.discard();
+    }
+
+    /// Try to read from stderr without waiting. See [`WasiStdout::try_read`].
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.try_read(buf)
+    }
+
+    /// Change the size of the stderr buffer. See [`WasiStdout::set_max_buf_size`].
+    pub fn set_max_buf_size(&self, max_buf_size: usize) -> Result<(), NewError> {
+        check_buf_size("stderr", max_buf_size)?;
+        self.inner.set_max_buf_size(max_buf_size);
+        Ok(())
+    }
+
+    /// Save the output that hasn't been read yet, see [`PipeSnapshot`].
+    pub fn snapshot(&self) -> PipeSnapshot {
+        self.inner.snapshot()
+    }
+
+    /// Replace whatever is buffered in stderr with a snapshot. See [`WasiStdin::restore`].
+    pub fn restore(&self, snapshot: &PipeSnapshot) -> Result<(), NewError> {
+        check_buf_size("stderr", snapshot.max_buf_size)?;
+        self.inner.restore(snapshot);
+        Ok(())
+    }
+}
 impl AsyncRead for WasiStderr {
     fn poll_read(
         self: Pin<&mut Self>,
@@ -119,6 +1856,50 @@ impl AsyncRead for WasiStderr {
 }
 
 /// A wasi process. See crate documentation for more details and examples.
+///
+/// # Cancellation
+///
+/// Dropping a `WasiProcess`, e.g. when it loses a `select!`, closes all of its pipes. The host
+/// ends see that like the module exiting: `WasiStdout` and `WasiStderr` get EOF after whatever
+/// was already written, and writing to a `WasiStdin` fails. A module that's still running gets
+/// EOF on its next read and a broken pipe error on its next write, so it can wind down on its
+/// own instead of waiting on a pipe nobody's using anymore.
+///
+/// Note that a process created with [`with_function`](Self::with_function) runs the module to
+/// completion within a single poll, so it can only be dropped before or after that.
+/// [`new_blocking`](Self::new_blocking) and
+/// [`with_function_blocking`](Self::with_function_blocking) processes can be dropped while the
+/// module runs on the blocking thread pool:
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use std::time::Duration;
+/// # use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasmer_wasi::WasiState;
+/// use wasi_process2::{MaxBufSize, WasiProcess};
+/// # let mut store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// # let mut state = WasiState::new("echo");
+/// # wasi_process2::add_stdio(&mut state);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let mut wasi = WasiProcess::new_blocking(store, &instance, MaxBufSize::default())?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// tokio::select! {
+///     _ = &mut wasi => unreachable!("the module waits for stdin"),
+///     _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+/// }
+/// drop(wasi);
+/// assert!(stdin.write_all(b"hello").await.is_err());
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "");
+/// # Ok(())
+/// # }
+/// ```
 #[must_use = "WasiProcess does nothing without being polled or spawned. Try calling `.spawn()`"]
 pub struct WasiProcess {
     /// An stdin reader for the wasi process
@@ -127,27 +1908,215 @@ pub struct WasiProcess {
     pub stdout: Option<WasiStdout>,
     /// An stderr writer for the wasi process
     pub stderr: Option<WasiStderr>,
-    handle: Pin<Box<dyn Future<Output = Result<(), RuntimeError>> + Send + Sync>>,
+    pipes: Pipes,
+    start: Option<StartFn>,
+    handle: Option<ProcessFuture>,
+    instance: Option<wasmer::Instance>,
+    store: StoreSlot,
+    /// The store until the process starts, for [`call_in_scope`](Self::call_in_scope).
+    unstarted_store: StoreSlot,
+    /// What the function returned, for [`with_function_args`](Self::with_function_args).
+    returns: ReturnSlot,
+    program_name: Option<String>,
+    /// The task started by [`feed_stdin`](Self::feed_stdin), aborted once the process is done.
+    stdin_feed: Option<task::AbortHandle>,
+    /// See [`shutdown_timeout`](Self::shutdown_timeout).
+    shutdown_timeout: Option<Duration>,
+    /// See [`timeout`](Self::timeout).
+    timeout: Option<Duration>,
+    /// The task started once the process starts running, if it has a timeout, which stops it
+    /// with [`Stop::TimedOut`] when that runs out. Aborted once the process is done.
+    timeout_watchdog: Option<task::AbortHandle>,
+    /// The budget set by [`limit_output_bytes`](Self::limit_output_bytes).
+    output_limit: Option<Arc<OutputLimit>>,
+    /// The limit set by [`WasiProcessBuilder::max_memory_pages`].
+    memory_limit: Option<Arc<MemoryLimit>>,
+    /// The interval set by [`WasiProcessBuilder::yield_interval`].
+    yield_interval: Option<NonZeroU64>,
+    /// What stopped the process from the outside, like being [killed](Self::kill), if anything.
+    stop: Arc<watch::Sender<Option<Stop>>>,
+    /// The pipes given to the module with [`add_stdio_with_pipes`] that it's used so far.
+    shared: SharedPipes,
+    /// See [`kill_on_drop`](Self::kill_on_drop).
+    kill_on_drop: bool,
+    /// Whether the module waits at its next stdio call, see [`SpawnHandle::pause`].
+    paused: Arc<watch::Sender<bool>>,
+    /// Where the result is sent for [`status_handle`](Self::status_handle).
+    status: Option<watch::Sender<Option<Result<i32, RuntimeError>>>>,
+    /// The result of the process once [`poll_io`](Self::poll_io) has seen it finish, until it's
+    /// been reported; `Some(None)` after that.
+    io_exit: Option<Option<Result<ExitStatus, RuntimeError>>>,
 }
 
-/// A struct to configure the sizes of the internal buffers used for stdio.
-#[derive(Debug, Copy, Clone)]
-pub struct MaxBufSize {
-    /// The maximum size of the internal buffer for stdin
-    pub stdin: usize,
-    /// The maximum size of the internal buffer for stdout
-    pub stdout: usize,
-    /// The maximum size of the internal buffer for stderr
-    pub stderr: usize,
-}
+type ProcessFuture = Pin<Box<dyn Future<Output = Result<(), RuntimeError>> + Send + Sync>>;
+type StartFn = Box<dyn FnOnce(ModuleStdio) -> ProcessFuture + Send + Sync>;
 
-const DEFAULT_BUF_SIZE: usize = 1024;
+/// Where a process puts its store back once the module has returned, so that it can be
+/// inspected afterwards.
+#[derive(Clone, Default)]
+struct StoreSlot(Arc<Mutex<Option<BoxedStore>>>);
 
-impl Default for MaxBufSize {
-    fn default() -> Self {
-        MaxBufSize {
-            stdin: DEFAULT_BUF_SIZE,
-            stdout: DEFAULT_BUF_SIZE,
+type BoxedStore = Box<dyn AsStoreMut + Send + Sync>;
+
+/// Where a process puts what its function returned, see [`SpawnHandle::return_values`].
+type ReturnSlot = Arc<Mutex<Option<Box<[wasmer::Value]>>>>;
+
+impl StoreSlot {
+    fn put(&self, store: BoxedStore) {
+        *self.0.lock() = Some(store);
+    }
+
+    fn take(&self) -> BoxedStore {
+        self.0
+            .lock()
+            .take()
+            .expect("the store is only taken out while the module is running")
+    }
+}
+
+impl fmt::Debug for StoreSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StoreSlot")
+            .field("filled", &self.0.lock().is_some())
+            .finish()
+    }
+}
+
+/// A struct to configure the sizes of the internal buffers used for stdio. None of them can be
+/// 0, since nothing could ever be written to that stream; creating a process with one returns
+/// [`NewError::ZeroBufSize`].
+///
+/// Small buffers keep less memory around per process, but cost throughput, since every
+/// buffer's worth of data takes a round trip through the pipe's lock and a wakeup of the other
+/// end. `cargo bench --bench throughput` measures that for a few sizes. A buffer starts out
+/// empty and grows as it's written to; see [`WasiProcess::preallocate_buffers`] to allocate
+/// them up front instead.
+///
+/// Small buffers mean the module and the host take turns on a pipe often. Either end that's made
+/// a lot of reads or writes in a row gives the other one a turn, so an echo-style module can't
+/// starve the task reading its output, even on a single worker thread:
+/// ```
+/// # #[tokio::main(flavor = "multi_thread", worker_threads = 1)]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let buf_size = MaxBufSize { stdin: 64, stdout: 64, stderr: 64 };
+/// let mut wasi = WasiProcessBuilder::new("echo", module)
+///     .max_buf_size(buf_size)
+///     .build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// let handle = wasi.spawn();
+/// let input = vec![b'x'; 1 << 20];
+/// let writer = tokio::spawn(async move {
+///     stdin.write_all(&input).await?;
+///     stdin.shutdown().await
+/// });
+/// let mut out = Vec::new();
+/// stdout.read_to_end(&mut out).await?;
+/// writer.await??;
+/// handle.await?;
+/// assert_eq!(out.len(), (1 << 20) + 4);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// A buffer of a single byte makes the ends hand off to each other on every byte, which is
+/// where a lost wakeup would leave a process stuck. Lots of them at once get through it:
+/// ```
+/// # #[tokio::main(flavor = "multi_thread", worker_threads = 4)]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// use std::time::Duration;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let buf_size = MaxBufSize { stdin: 1, stdout: 1, stderr: 1 };
+/// let mut runs = Vec::new();
+/// for i in 0..16u8 {
+///     let store = wasmer::Store::new(engine.clone());
+///     let mut wasi = WasiProcessBuilder::new("echo", module.clone())
+///         .max_buf_size(buf_size)
+///         .build(store)?;
+///     let mut stdin = wasi.stdin.take().unwrap();
+///     let mut stdout = wasi.stdout.take().unwrap();
+///     let handle = wasi.spawn();
+///     let writer = tokio::spawn(async move {
+///         stdin.write_all(&[i; 4096]).await?;
+///         stdin.shutdown().await
+///     });
+///     runs.push(tokio::spawn(async move {
+///         let mut out = Vec::new();
+///         stdout.read_to_end(&mut out).await?;
+///         writer.await??;
+///         handle.await?;
+///         Ok::<_, Box<dyn std::error::Error + Send + Sync>>(out)
+///     }));
+/// }
+/// for (i, run) in runs.into_iter().enumerate() {
+///     // a lost wakeup hangs instead of failing, so give up eventually
+///     let out = tokio::time::timeout(Duration::from_secs(60), run).await???;
+///     assert_eq!(out[..4096], [i as u8; 4096]);
+///     assert_eq!(&out[4096..], b"END\n");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// A size of 0 is rejected up front, rather than leaving the module stuck on its first write:
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use wasi_process2::{BuildError, MaxBufSize, NewError, WasiProcessBuilder};
+/// # let store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let err = WasiProcessBuilder::new("progg", module)
+///     .max_buf_size(MaxBufSize { stdout: 0, ..MaxBufSize::default() })
+///     .build(store)
+///     .err()
+///     .unwrap();
+/// assert!(matches!(err, BuildError::Process(NewError::ZeroBufSize("stdout"))));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct MaxBufSize {
+    /// The maximum size of the internal buffer for stdin
+    pub stdin: usize,
+    /// The maximum size of the internal buffer for stdout
+    pub stdout: usize,
+    /// The maximum size of the internal buffer for stderr
+    pub stderr: usize,
+}
+
+const DEFAULT_BUF_SIZE: usize = 1024;
+
+impl MaxBufSize {
+    /// Make sure none of the sizes are 0, since nothing could ever be written to that pipe.
+    fn check(&self) -> Result<(), NewError> {
+        check_buf_size("stdin", self.stdin)?;
+        check_buf_size("stdout", self.stdout)?;
+        check_buf_size("stderr", self.stderr)
+    }
+}
+
+/// Make sure the buffer size for `stream` isn't 0, like [`MaxBufSize::check`].
+fn check_buf_size(stream: &'static str, size: usize) -> Result<(), NewError> {
+    if size == 0 {
+        Err(NewError::ZeroBufSize(stream))
+    } else {
+        Ok(())
+    }
+}
+
+impl Default for MaxBufSize {
+    fn default() -> Self {
+        MaxBufSize {
+            stdin: DEFAULT_BUF_SIZE,
+            stdout: DEFAULT_BUF_SIZE,
             stderr: DEFAULT_BUF_SIZE,
         }
     }
@@ -155,55 +2124,2168 @@ impl Default for MaxBufSize {
 
 impl WasiProcess {
     /// Create a WasiProcess from a wasm instance. See the crate documentation for more details.
-    /// Returns an error if the instance doesn't have a `_start` function exported.
+    /// Returns an error if the instance doesn't have a `_start` function exported, or if it takes
+    /// parameters or returns anything.
+    ///
+    /// The store the instance lives in is moved into the process, and is dropped along with it.
+    /// Anything that implements `AsStoreMut` works, so a `&'static mut Store` can be passed too.
+    ///
+    /// The instance can have any imports alongside the wasi ones. Host functions called by the
+    /// module while the process runs can use this process's stdio through [`Stdin`], [`Stdout`]
+    /// and [`Stderr`], just like the module does; see [`scope_stdio`](Self::scope_stdio) for
+    /// using them from anywhere else.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use tokio::io::AsyncReadExt;
+    /// # use wasmer_wasi::WasiState;
+    /// use std::io::Write;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// # let mut store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (import "env" "log" (func $log))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (call $log) (call $log)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// # let mut state = WasiState::new("progg");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// let mut imports = env.import_object(&mut store, &module)?;
+    /// let log = wasmer::Function::new_typed(&mut store, || {
+    ///     wasi_process2::Stdout.write_all(b"logged by the host\n").unwrap();
+    /// });
+    /// imports.define("env", "log", log);
+    /// let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let mut wasi = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn().await?;
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "logged by the host\nlogged by the host\n");
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn new(
-        store: &'static mut once_cell::sync::Lazy<wasmer::Store>,
+        store: impl AsStoreMut + Send + Sync + 'static,
         instance: &wasmer::Instance,
         buf_size: MaxBufSize,
-    ) -> Result<Self, wasmer::ExportError> {
-        let start = instance.exports.get_function("_start")?.clone();
-        Ok(Self::with_function(store, start, buf_size))
+    ) -> Result<Self, NewError> {
+        Self::new_with_entry(store, instance, "_start", buf_size)
+    }
+
+    /// Create a WasiProcess from a wasm instance, using the exported function `entry_name` as the
+    /// entry point instead of `_start`. Returns an error naming `entry_name` if the instance
+    /// doesn't export it, or the same errors as [`with_function`](Self::with_function).
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use tokio::io::AsyncReadExt;
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// # let mut store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 12) "hi\n")
+    ///     (func (export "main")
+    ///         (i32.store (i32.const 0) (i32.const 12))
+    ///         (i32.store (i32.const 4) (i32.const 3))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// # let mut state = WasiState::new("progg");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// # let other = wasmer::Store::default();
+    /// let err = WasiProcess::new(other, &instance, MaxBufSize::default()).err().unwrap();
+    /// assert_eq!(err.to_string(), "missing entry point export `_start`");
+    ///
+    /// let mut wasi = WasiProcess::new_with_entry(store, &instance, "main", MaxBufSize::default())?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.spawn();
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "hi\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_with_entry(
+        store: impl AsStoreMut + Send + Sync + 'static,
+        instance: &wasmer::Instance,
+        entry_name: &str,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        let start = get_start(instance, entry_name)?;
+        let mut process = Self::with_function(store, start, buf_size)?;
+        process.instance = Some(instance.clone());
+        Ok(process)
     }
 
     /// Create a WasiProcess from a wasm instance, given a `_start` function. See the crate
     /// documentation for more details.
+    ///
+    /// The module is run with `task::block_in_place`, so the process must be polled from a
+    /// multi-threaded tokio runtime. Use [`with_function_blocking`](Self::with_function_blocking)
+    /// for a current-thread runtime. Any number of worker threads works, since the module
+    /// waiting on a pipe doesn't stop the host tasks on the other end from running.
+    ///
+    /// The same goes for a module that computes for a long time without touching its stdio:
+    /// `block_in_place` hands the worker's other tasks off to a new worker thread, so the rest of
+    /// the runtime carries on and the module doesn't need to yield to it. What does wait are
+    /// other futures in the same task as the process, e.g. ones `join!`ed with it, which is why
+    /// the examples [`spawn`](Self::spawn) the process instead. A module that calls
+    /// `sched_yield` can take turns with those too, see
+    /// [`WasiProcessBuilder::yield_interval`].
+    ///
+    /// Returns [`NewError::WrongSignature`] if the function takes parameters or returns
+    /// anything.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// # let mut store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// # let mut state = WasiState::new("echo");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let start = instance.exports.get_function("_start")?.clone();
+    /// // tiny buffers, so the module has to wait on the host over and over
+    /// let buf_size = MaxBufSize { stdin: 3, stdout: 5, stderr: 5 };
+    /// let mut wasi = WasiProcess::with_function(store, start, buf_size)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let write = tokio::spawn(async move {
+    ///     for i in 0..10_000u32 {
+    ///         stdin.write_all(&[i as u8]).await?;
+    ///     }
+    ///     stdin.shutdown().await
+    /// });
+    /// let read = async {
+    ///     let mut out = Vec::new();
+    ///     stdout.read_to_end(&mut out).await?;
+    ///     Ok::<_, std::io::Error>(out)
+    /// };
+    /// let out = tokio::time::timeout(Duration::from_secs(60), read).await??;
+    /// write.await??;
+    /// handle.await?;
+    /// assert_eq!(out.len(), 10_004);
+    /// assert!((0..10_000u32).all(|i| out[i as usize] == i as u8));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Other tasks keep running while a module spins, even with a single worker thread:
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let rt = tokio::runtime::Builder::new_multi_thread().worker_threads(1).build()?;
+    /// # rt.block_on(async {
+    /// # let store = wasmer::Store::default();
+    /// // writes a byte to stdout, then spins forever without any more calls
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) "!")
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 1))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///         (loop $spin (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("spin", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let mut handle = wasi.spawn();
+    /// // the module is spinning once it's written its byte
+    /// stdout.read_exact(&mut [0]).await?;
+    /// // and another task still gets to run
+    /// tokio::spawn(async { tokio::task::yield_now().await }).await?;
+    /// assert!(handle.try_wait().is_none());
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// # })?;
+    /// # // the module never returns, so don't wait for it
+    /// # rt.shutdown_background();
+    /// # Ok(())
+    /// # }
+    /// ```
     pub fn with_function(
-        store: &'static mut once_cell::sync::Lazy<wasmer::Store>,
-        start_function: wasmer::Function, buf_size: MaxBufSize) -> Self {
-        let stdin = LockPipe::new(buf_size.stdin);
-        let stdout = LockPipe::new(buf_size.stdout);
-        let stderr = LockPipe::new(buf_size.stderr);
-        let handle = STDIN.scope(
-            stdin.clone(),
-            STDOUT.scope(
-                stdout.clone(),
-                STDERR.scope(stderr.clone(), async move {
-                    task::block_in_place(|| start_function.call(&mut store.as_store_mut(), &[]).map(drop))
-                }),
-            ),
-        );
+        store: impl AsStoreMut + Send + Sync + 'static,
+        start_function: wasmer::Function,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        check_start(&store, &start_function)?;
+        buf_size.check()?;
+        Ok(Self::with_call(store, buf_size, move |store| {
+            start_function.call(store, &[]).map(drop)
+        }))
+    }
+
+    /// Create a WasiProcess that calls `function` with `args`, like
+    /// [`with_function`](Self::with_function), for an export that takes arguments or returns
+    /// values rather than following the `_start` convention. Once it's returned, what it
+    /// returned can be had from [`SpawnHandle::return_values`].
+    ///
+    /// Returns [`NewError::WrongSignature`] if the types of `args` don't match the function's
+    /// parameters.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// use wasmer::Value;
+    /// # let mut store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "add") (param i32 i32) (result i32)
+    ///         (i32.add (local.get 0) (local.get 1))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// # let mut state = WasiState::new("add");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let add = instance.exports.get_function("add")?.clone();
+    /// let args = vec![Value::I32(2), Value::I32(3)];
+    /// let wasi = WasiProcess::with_function_args(store, add, args, MaxBufSize::default())?;
+    /// let mut handle = wasi.spawn();
+    /// (&mut handle).await?;
+    /// assert_eq!(handle.return_values().as_deref(), Some(&[Value::I32(5)][..]));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_function_args(
+        store: impl AsStoreMut + Send + Sync + 'static,
+        function: wasmer::Function,
+        args: Vec<wasmer::Value>,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        let found = function.ty(&store);
+        let params: Vec<_> = args.iter().map(wasmer::Value::ty).collect();
+        if found.params() != params.as_slice() {
+            let expected = wasmer::FunctionType::new(params, found.results());
+            return Err(NewError::WrongSignature { expected, found });
+        }
+        buf_size.check()?;
+        let returns = ReturnSlot::default();
+        let mut process = Self::with_call(store, buf_size, {
+            let returns = returns.clone();
+            move |store| {
+                let values = function.call(store, &args)?;
+                *returns.lock() = Some(values);
+                Ok(())
+            }
+        });
+        process.returns = returns;
+        Ok(process)
+    }
+
+    /// Create a WasiProcess from a wasm instance whose module was compiled with wasmer's
+    /// [`Metering`](wasmer_middlewares::Metering) middleware, giving it a budget of `points` to
+    /// run `_start` with. If the budget runs out, the process fails with
+    /// [`SpawnError::OutOfFuel`]. Returns an error if the instance doesn't have a `_start`
+    /// function or the metering globals exported.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use std::sync::Arc;
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, SpawnError, WasiProcess};
+    /// use wasmer::CompilerConfig;
+    /// use wasmer_middlewares::Metering;
+    /// let mut compiler = wasmer::Cranelift::default();
+    /// compiler.push_middleware(Arc::new(Metering::new(0, |_| 1)));
+    /// let mut store = wasmer::Store::new(compiler);
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (loop $spin (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// # let mut state = WasiState::new("spin");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let wasi = WasiProcess::new_metered(store, &instance, MaxBufSize::default(), 10_000)?;
+    /// assert!(matches!(wasi.spawn().await, Err(SpawnError::OutOfFuel)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_metered(
+        mut store: impl AsStoreMut + Send + Sync + 'static,
+        instance: &wasmer::Instance,
+        buf_size: MaxBufSize,
+        points: u64,
+    ) -> Result<Self, NewError> {
+        let start = get_start(instance, "_start")?;
+        check_start(&store, &start)?;
+        buf_size.check()?;
+        // checked here, since the metering functions panic if they're missing
+        instance
+            .exports
+            .get_global("wasmer_metering_remaining_points")?;
+        instance
+            .exports
+            .get_global("wasmer_metering_points_exhausted")?;
+        let instance = instance.clone();
+        set_remaining_points(&mut store, &instance, points);
+        let mut process = Self::with_call(store, buf_size, {
+            let instance = instance.clone();
+            move |store| {
+                let res = start.call(store, &[]).map(drop);
+                match res {
+                    Err(_)
+                        if get_remaining_points(store, &instance) == MeteringPoints::Exhausted =>
+                    {
+                        Err(RuntimeError::user(Box::new(OutOfFuel)))
+                    }
+                    res => res,
+                }
+            }
+        });
+        process.instance = Some(instance);
+        Ok(process)
+    }
+
+    /// Run `call` with `task::block_in_place`, like [`with_function`](Self::with_function), or
+    /// on the blocking thread pool in turns with the process if the module
+    /// [yields](WasiProcessBuilder::yield_interval).
+    fn with_call(
+        store: impl AsStoreMut + Send + Sync + 'static,
+        buf_size: MaxBufSize,
+        call: impl FnOnce(&mut wasmer::StoreMut) -> Result<(), RuntimeError> + Send + Sync + 'static,
+    ) -> Self {
+        let slot = StoreSlot::default();
+        let unstarted = StoreSlot::default();
+        unstarted.put(Box::new(store));
+        let mut process = Self::with_start(buf_size, slot.clone(), {
+            let unstarted = unstarted.clone();
+            move |stdio| {
+                let run = move || {
+                    let mut store = unstarted.take();
+                    let res = call(&mut store.as_store_mut());
+                    stdio::flush_staged();
+                    slot.put(store);
+                    res
+                };
+                match stdio.turns.clone() {
+                    Some(turns) => Box::pin(turns.run(move || STDIO.sync_scope(stdio, run))),
+                    None => Box::pin(STDIO.scope(stdio, async { task::block_in_place(run) })),
+                }
+            }
+        });
+        process.unstarted_store = unstarted;
+        process
+    }
+
+    /// Create a WasiProcess from a wasm instance that runs on tokio's blocking thread pool
+    /// instead of with `block_in_place`. Returns an error if the instance doesn't have a `_start`
+    /// function exported.
+    ///
+    /// See [`with_function_blocking`](Self::with_function_blocking) for the threading model.
+    ///
+    /// # Examples
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use tokio::io::AsyncReadExt;
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// let rt = tokio::runtime::Builder::new_current_thread().build()?;
+    /// let out = rt.block_on(async {
+    ///     # let mut store = wasmer::Store::default();
+    ///     # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    ///     # let mut state = WasiState::new("progg");
+    ///     # wasi_process2::add_stdio(&mut state);
+    ///     # let mut env = state.finalize(&mut store)?;
+    ///     # let imports = env.import_object(&mut store, &module)?;
+    ///     # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    ///     # env.initialize(&mut store, &instance)?;
+    ///     let mut wasi = WasiProcess::new_blocking(store, &instance, MaxBufSize::default())?;
+    ///     let mut stdout = wasi.stdout.take().unwrap();
+    ///     let handle = wasi.spawn();
+    ///     let mut out = String::new();
+    ///     stdout.read_to_string(&mut out).await?;
+    ///     handle.await?;
+    ///     Ok::<_, Box<dyn std::error::Error>>(out)
+    /// })?;
+    /// assert_eq!(out, "Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_blocking(
+        store: impl AsStoreMut + Send + Sync + 'static,
+        instance: &wasmer::Instance,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        let start = get_start(instance, "_start")?;
+        let mut process = Self::with_function_blocking(store, start, buf_size)?;
+        process.instance = Some(instance.clone());
+        Ok(process)
+    }
+
+    /// Create a WasiProcess that runs the given `_start` function on tokio's blocking thread
+    /// pool, via `task::spawn_blocking`.
+    ///
+    /// Unlike [`with_function`](Self::with_function), this doesn't use `block_in_place`, so it
+    /// works on a current-thread runtime. The stdio pseudo-files still block the thread while the
+    /// module waits on a pipe, which is fine here since that's the dedicated blocking thread
+    /// rather than a runtime worker. The host side of the pipes is driven by whatever tasks you
+    /// poll the `WasiStdin`/`WasiStdout`/`WasiStderr` from.
+    ///
+    /// Note that dropping the process future after it's been polled doesn't stop the module;
+    /// it keeps running on the blocking thread until `_start` returns.
+    ///
+    /// Returns [`NewError::WrongSignature`] if the function takes parameters or returns
+    /// anything.
+    pub fn with_function_blocking(
+        store: impl AsStoreMut + Send + Sync + 'static,
+        start_function: wasmer::Function,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        check_start(&store, &start_function)?;
+        buf_size.check()?;
+        let slot = StoreSlot::default();
+        let unstarted = StoreSlot::default();
+        unstarted.put(Box::new(store));
+        let mut process = Self::with_start(buf_size, slot.clone(), {
+            let unstarted = unstarted.clone();
+            move |stdio| {
+                Box::pin(async move {
+                    let res = task::spawn_blocking(move || {
+                        STDIO.sync_scope(stdio, || {
+                            let mut store = unstarted.take();
+                            let res = start_function
+                                .call(&mut store.as_store_mut(), &[])
+                                .map(drop);
+                            stdio::flush_staged();
+                            slot.put(store);
+                            res
+                        })
+                    })
+                    .await;
+                    match res {
+                        Ok(res) => res,
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    }
+                })
+            }
+        });
+        process.unstarted_store = unstarted;
+        Ok(process)
+    }
+
+    fn with_start(
+        buf_size: MaxBufSize,
+        store: StoreSlot,
+        start: impl FnOnce(ModuleStdio) -> ProcessFuture + Send + Sync + 'static,
+    ) -> Self {
+        let mut process = Self::with_stdio(buf_size, store);
+        process.start = Some(Box::new(start));
+        process
+    }
+
+    /// Set up the stdio pipes for a process, without anything to run yet.
+    fn with_stdio(buf_size: MaxBufSize, store: StoreSlot) -> Self {
+        let (stdin_reader, stdin) = LockPipe::new_pair(buf_size.stdin);
+        let (stdout, stdout_writer) = LockPipe::new_pair(buf_size.stdout);
+        let (stderr, stderr_writer) = LockPipe::new_pair(buf_size.stderr);
+        let mut pipes = Pipes::new();
+        pipes.insert(0, stdin_reader);
+        pipes.insert(1, stdout_writer);
+        pipes.insert(2, stderr_writer);
+        let paused = Arc::new(watch::channel(false).0);
 
         Self {
             stdin: Some(WasiStdin { inner: stdin }),
-            stdout: Some(WasiStdout { inner: stdout }),
+            stdout: Some(WasiStdout::new(stdout)),
             stderr: Some(WasiStderr { inner: stderr }),
-            handle: Box::pin(handle),
+            pipes,
+            start: None,
+            handle: None,
+            instance: None,
+            store,
+            unstarted_store: StoreSlot::default(),
+            returns: ReturnSlot::default(),
+            program_name: None,
+            stdin_feed: None,
+            shutdown_timeout: None,
+            timeout: None,
+            timeout_watchdog: None,
+            output_limit: None,
+            memory_limit: None,
+            yield_interval: None,
+            stop: Arc::new(watch::channel(None).0),
+            shared: SharedPipes::new(paused.subscribe()),
+            kill_on_drop: false,
+            paused,
+            status: None,
+            io_exit: None,
+        }
+    }
+
+    /// The name of the process, for telling processes apart in logs and errors. A
+    /// [`WasiProcessBuilder`] sets it to the program name; otherwise it's `None` until it's set
+    /// with [`set_name`](Self::set_name).
+    pub fn name(&self) -> Option<&str> {
+        self.program_name.as_deref()
+    }
+
+    /// Set the name of the process, see [`name`](Self::name).
+    pub fn set_name(&mut self, name: &str) -> &mut Self {
+        self.program_name = Some(name.to_owned());
+        self
+    }
+
+    /// Stop the module from writing more than `max` bytes to stdout and stderr combined, so a
+    /// runaway module can't produce gigabytes of output before anyone notices. Unlike the
+    /// buffer sizes, this counts everything written over the whole run, whether or not it's been
+    /// read yet.
+    ///
+    /// Once the budget is used up, the next write to either stream fails with a broken pipe
+    /// error and closes the stream, and the process fails with
+    /// [`SpawnError::OutputLimitExceeded`] when it finishes. Like with
+    /// [`spawn_idle_timeout`](Self::spawn_idle_timeout), a module that ignores the errors and
+    /// keeps going can't be stopped this way; use [`new_metered`](Self::new_metered) for that.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // writes "spam\n" to stdout until a write fails
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) "spam\n")
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 5))
+    ///         (loop $spam
+    ///             (br_if $spam (i32.eqz
+    ///                 (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("spam", module)
+    ///     .max_output_bytes(1000)
+    ///     .build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let mut out = Vec::new();
+    /// stdout.read_to_end(&mut out).await?;
+    /// assert_eq!(out.len(), 1000);
+    /// assert!(matches!(handle.await, Err(SpawnError::OutputLimitExceeded)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn limit_output_bytes(&mut self, max: u64) -> &mut Self {
+        let limit = Arc::new(OutputLimit::new(max));
+        for fd in [1, 2] {
+            if let Some(pipe) = self.pipes.get(&fd) {
+                pipe.set_output_limit(limit.clone());
+            }
+        }
+        self.output_limit = Some(limit);
+        self
+    }
+
+    /// Have the module's writes to stdout and stderr collected on its own thread until there are
+    /// `threshold` bytes of them, before they're put in the pipe. A module making lots of tiny
+    /// writes then takes the pipe's lock far less often, at the cost of its output showing up a
+    /// bit later. Whatever's left is written out before the module reads stdin, so that a prompt
+    /// shows up before the module waits for an answer, and when it returns. 0, the default,
+    /// writes everything right away.
+    ///
+    /// A write that's only been collected succeeds as far as the module is concerned, so if the
+    /// pipe turns out to be closed by the time it's written out, the module only gets an error
+    /// for a later write, or not at all.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // writes "." to stdout 1000 times, one byte at a time
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) ".")
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 1))
+    ///         (loop $write
+    ///             (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 1000))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut writes = Vec::new();
+    /// for threshold in [0, 256] {
+    ///     # let store = wasmer::Store::new(engine.clone());
+    ///     let mut wasi = WasiProcessBuilder::new("dots", module.clone())
+    ///         .coalesce_writes(threshold)
+    ///         .build(store)?;
+    ///     let mut stdout = wasi.stdout.take().unwrap();
+    ///     let handle = wasi.spawn();
+    ///     let mut out = Vec::new();
+    ///     stdout.read_to_end(&mut out).await?;
+    ///     handle.await?;
+    ///     assert_eq!(out, [b'.'; 1000]);
+    ///     writes.push(stdout.metrics().writes);
+    /// }
+    /// // every write takes the lock, vs. 3 batches of 256 and the rest when it returns
+    /// assert_eq!(writes, [1000, 4]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn coalesce_writes(&mut self, threshold: usize) -> &mut Self {
+        for fd in [1, 2] {
+            if let Some(pipe) = self.pipes.get_mut(&fd) {
+                pipe.set_coalesce(threshold);
+            }
+        }
+        self
+    }
+
+    /// Keep only the last `bytes` the module writes to stderr, like a ring buffer: once the
+    /// buffer is full, each write drops the oldest bytes to make room rather than waiting for
+    /// them to be read. A module that writes lots of diagnostics never blocks on stderr then,
+    /// and reading stderr after it exits gives the tail of what it wrote. This replaces the
+    /// stderr buffer size from [`MaxBufSize`], and does nothing if `stderr` is `None`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // writes the numbers 0 to 999 to stderr, each as 4 little-endian bytes
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 4))
+    ///         (loop $write
+    ///             (i32.store (i32.const 16) (local.get $i))
+    ///             (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 1000))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("noisy", module)
+    ///     .stderr_tail_bytes(42)
+    ///     .build(store)?;
+    /// let mut stderr = wasi.stderr.take().unwrap();
+    /// // nothing reads stderr while the module runs, and it still finishes
+    /// wasi.spawn().await?;
+    /// let mut tail = Vec::new();
+    /// stderr.read_to_end(&mut tail).await?;
+    /// let all: Vec<u8> = (0..1000u32).flat_map(u32::to_le_bytes).collect();
+    /// assert_eq!(tail, &all[all.len() - 42..]);
+    /// assert_eq!(stderr.metrics().bytes_written, 4000);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn stderr_tail_bytes(&mut self, bytes: usize) -> &mut Self {
+        if let Some(stderr) = &self.stderr {
+            stderr.inner.set_ring(bytes);
+        }
+        self
+    }
+
+    /// Have the module's stdin reads fail with `EAGAIN` when there's nothing to read yet, rather
+    /// than wait for the host to write something, like reading a pipe with `O_NONBLOCK` set. Once
+    /// stdin is closed and empty, reads get EOF as usual. The number of bytes waiting to be read
+    /// is reported through the file's `bytes_available` either way.
+    ///
+    /// wasmer-wasi doesn't pass the fd's flags on to the file, so this has to be set here rather
+    /// than by the module with `fd_fdstat_set_flags`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // writes the errno of its first read from stdin as a single byte, then reads until it gets
+    /// // something and echoes that
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 64))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (i32.store8 (i32.const 32)
+    ///             (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///         (i32.store (i32.const 16) (i32.const 32))
+    ///         (i32.store (i32.const 20) (i32.const 1))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24)))
+    ///         (loop $poll
+    ///             (br_if $poll (i32.eq
+    ///                 (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8))
+    ///                 (i32.const 6))))
+    ///         (i32.store (i32.const 4) (i32.load (i32.const 8)))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("poll", module)
+    ///     .nonblocking_stdin(true)
+    ///     .build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let mut errno = [0];
+    /// stdout.read_exact(&mut errno).await?;
+    /// // EAGAIN, since nothing's been written yet
+    /// assert_eq!(errno, [6]);
+    /// stdin.write_all(b"hi").await?;
+    /// handle.await?;
+    /// let mut out = Vec::new();
+    /// stdout.read_to_end(&mut out).await?;
+    /// assert_eq!(out, b"hi");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn nonblocking_stdin(&mut self, nonblocking: bool) -> &mut Self {
+        if let Some(pipe) = self.pipes.get_mut(&0) {
+            pipe.set_nonblocking(nonblocking);
+        }
+        self
+    }
+
+    /// Allocate the buffers of the process's pipes up front, so that writes that fit in a pipe's
+    /// [`MaxBufSize`] never have to grow its buffer while the process runs. Otherwise a buffer
+    /// starts out empty and grows as it fills up. Each buffer takes twice its `MaxBufSize`, since
+    /// the bytes in it are only moved back to the start once at least as many have been read as
+    /// are left, and a pipe whose size is changed later with `set_max_buf_size` isn't
+    /// reallocated. [`PipeMetrics::allocations`] counts the writes that grew a buffer anyway.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{MaxBufSize, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // writes "." to stdout 1000 times, one byte at a time
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) ".")
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 1))
+    ///         (loop $write
+    ///             (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 1000))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut allocations = Vec::new();
+    /// for preallocate in [false, true] {
+    ///     # let store = wasmer::Store::new(engine.clone());
+    ///     let mut wasi = WasiProcessBuilder::new("dots", module.clone())
+    ///         .max_buf_size(MaxBufSize { stdout: 64, ..MaxBufSize::default() })
+    ///         .preallocate_buffers(preallocate)
+    ///         .build(store)?;
+    ///     let mut stdout = wasi.stdout.take().unwrap();
+    ///     let handle = wasi.spawn();
+    ///     let mut out = Vec::new();
+    ///     let mut chunk = [0; 10];
+    ///     loop {
+    ///         match stdout.read(&mut chunk).await? {
+    ///             0 => break,
+    ///             n => out.extend_from_slice(&chunk[..n]),
+    ///         }
+    ///     }
+    ///     handle.await?;
+    ///     assert_eq!(out, [b'.'; 1000]);
+    ///     allocations.push(stdout.metrics().allocations);
+    /// }
+    /// // at the very least, the first write has to allocate an empty buffer
+    /// assert!(allocations[0] > 0);
+    /// assert_eq!(allocations[1], 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn preallocate_buffers(&mut self) -> &mut Self {
+        for pipe in self.pipes.values() {
+            pipe.preallocate();
+        }
+        self
+    }
+
+    /// Run `f` with this process's stdio set up for the current thread, so [`Stdin`],
+    /// [`Stdout`] and [`Stderr`] use its pipes, like they do for the module and its host
+    /// functions while the process runs. Outside of that, they fail with an I/O error, which a
+    /// module calling them without going through a process sees as `EIO`.
+    ///
+    /// This is for host code that wants to use the process's stdio without going through
+    /// [`stdin`](Self::stdin), [`stdout`](Self::stdout) and [`stderr`](Self::stderr), e.g.
+    /// shared helpers that are also called from host functions.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::io::{Read, Write};
+    /// use tokio::io::AsyncReadExt;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// assert!(wasi_process2::Stdin.read(&mut [0; 8]).is_err());
+    /// assert!(wasi_process2::Stdout.write_all(b"nowhere\n").is_err());
+    ///
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// wasi.scope_stdio(|| wasi_process2::Stdout.write_all(b"banner\n"))?;
+    /// wasi.spawn().await?;
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "banner\nHello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn scope_stdio<R>(&self, f: impl FnOnce() -> R) -> R {
+        let stdio = ModuleStdio {
+            pipes: self
+                .pipes
+                .iter()
+                .map(|(&fd, pipe)| (fd, pipe.handle()))
+                .collect(),
+            shared: self.shared.clone(),
+            turns: None,
+        };
+        STDIO.sync_scope(stdio, || {
+            let res = f();
+            stdio::flush_staged();
+            res
+        })
+    }
+
+    /// Call `function` with `args` before the process is started, with this process's stdio set
+    /// up like [`scope_stdio`](Self::scope_stdio) does, so that whatever it writes goes to the
+    /// same pipes the module's `_start` writes to. This is for modules that export more than one
+    /// entry point, e.g. an `init` function that should run before the main one.
+    ///
+    /// This blocks the current thread until the function returns, so anything it writes has to
+    /// fit in the pipe's buffer, since nothing can read it in the meantime. Returns an error if
+    /// the store isn't available to call the function with, which is the case for a process
+    /// that's already been started and for a [`LocalWasiProcess`].
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{MaxBufSize, WasiProcess};
+    /// # use wasmer_wasi::WasiState;
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (data (i32.const 16) "hello from handler\nhello from _start\n")
+    ///     (func $write (param $ptr i32) (param $len i32)
+    ///         (i32.store (i32.const 0) (local.get $ptr))
+    ///         (i32.store (i32.const 4) (local.get $len))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+    ///     (func (export "handler") (param $times i32) (result i32)
+    ///         (call $write (i32.const 16) (i32.const 19))
+    ///         (i32.mul (local.get $times) (i32.const 2)))
+    ///     (func (export "_start")
+    ///         (call $write (i32.const 35) (i32.const 18))))"#;
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, wat)?;
+    /// # let mut state = WasiState::new("handler");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let handler = instance.exports.get_function("handler")?.clone();
+    /// let mut wasi = WasiProcess::new(store, &instance, MaxBufSize::default())?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let ret = wasi.call_in_scope(&handler, &[wasmer::Value::I32(21)])?;
+    /// assert_eq!(*ret, [wasmer::Value::I32(42)]);
+    /// wasi.spawn().await?;
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "hello from handler\nhello from _start\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn call_in_scope(
+        &mut self,
+        function: &wasmer::Function,
+        args: &[wasmer::Value],
+    ) -> Result<Box<[wasmer::Value]>, RuntimeError> {
+        let mut store = self.unstarted_store.0.lock();
+        let store = store.as_mut().ok_or_else(|| {
+            RuntimeError::new("the store isn't available to call the function with")
+        })?;
+        self.scope_stdio(|| function.call(&mut store.as_store_mut(), args))
+    }
+
+    /// Get a handle that can be cloned and awaited any number of times, from any number of
+    /// tasks, for the result of the process. It's unaffected by whatever is done with the
+    /// process or its [`SpawnHandle`], so e.g. a supervisor and a logger can both wait for the
+    /// same process.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let status = wasi.status_handle();
+    /// let waiters = (0..2).map(|_| {
+    ///     let mut status = status.clone();
+    ///     tokio::spawn(async move { status.wait().await.unwrap().unwrap() })
+    /// });
+    /// let waiters: Vec<_> = waiters.collect();
+    /// wasi.spawn();
+    /// for waiter in waiters {
+    ///     assert_eq!(waiter.await?, 0);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn status_handle(&mut self) -> StatusHandle {
+        let status = self.status.get_or_insert_with(|| watch::channel(None).0);
+        StatusHandle {
+            status: status.subscribe(),
         }
     }
 
+    /// Finish up after the process returned `res`: close the module's ends of the pipes it was
+    /// given with [`add_stdio_with_pipes`], replace `res` with a [`Killed`] error if it was
+    /// killed, a [`TimedOut`] error if it ran out of time, an [`OutputLimitExceeded`] error if it
+    /// went over its output limit, or an [`OutOfMemory`] error if it failed after running into
+    /// its memory limit, and send it to any [`StatusHandle`]s.
+    fn finish(&self, res: Result<(), RuntimeError>) -> Result<ExitStatus, RuntimeError> {
+        if let Some(watchdog) = &self.timeout_watchdog {
+            watchdog.abort();
+        }
+        self.shared.close_ends();
+        let res = match &self.output_limit {
+            Some(limit) if limit.exceeded() => {
+                Err(RuntimeError::user(Box::new(OutputLimitExceeded)))
+            }
+            _ => res,
+        };
+        let res = match (&self.memory_limit, res) {
+            // a module that calls `proc_exit` has dealt with it, so its exit code is kept
+            (Some(limit), Err(e)) if limit.exceeded() && !e.is::<WasiError>() => {
+                Err(RuntimeError::user(Box::new(OutOfMemory)))
+            }
+            (_, res) => res,
+        };
+        let stop = *self.stop.borrow();
+        let res = match stop {
+            Some(stop) => Err(stop.error()),
+            None => res,
+        };
+        let res = exit_code(res).map(ExitStatus);
+        if let Some(status) = &self.status {
+            status.send_replace(Some(res.clone().map(|status| status.code())));
+        }
+        res
+    }
+
+    /// Create the host end of an extra pipe that the module reads from, registered with
+    /// [`add_pipes`] as [`Direction::Read`]. If there was already a pipe for `fd`, it's closed
+    /// and replaced.
+    pub fn pipe_writer(&mut self, fd: u32, max_buf_size: usize) -> WasiStdin {
+        WasiStdin {
+            inner: self.new_pipe(fd, max_buf_size, End::Write),
+        }
+    }
+
+    /// Create the host end of an extra pipe that the module writes to, registered with
+    /// [`add_pipes`] as [`Direction::Write`]. If there was already a pipe for `fd`, it's closed
+    /// and replaced.
+    pub fn pipe_reader(&mut self, fd: u32, max_buf_size: usize) -> WasiStdout {
+        WasiStdout::new(self.new_pipe(fd, max_buf_size, End::Read))
+    }
+
+    /// Replace stdin with a pipe that already holds `data` and is closed, so the module reads
+    /// `data` and then EOF. `self.stdin` is set to `None`.
+    fn seed_stdin(&mut self, data: &[u8], max_buf_size: usize) {
+        self.stdin = None;
+        self.pipes.insert(0, LockPipe::seeded(data, max_buf_size));
+    }
+
+    /// Create a pipe for `fd`, keeping the module's end and returning the host's `end`.
+    fn new_pipe(&mut self, fd: u32, max_buf_size: usize, end: End) -> LockPipe {
+        let (reader, writer) = LockPipe::new_pair(max_buf_size);
+        let (host, module) = match end {
+            End::Read => (reader, writer),
+            End::Write => (writer, reader),
+        };
+        self.pipes.insert(fd, module);
+        host
+    }
+
+    /// Kill the process, see [`SpawnHandle::kill`]. Awaiting the process itself, rather than
+    /// its [`SpawnHandle`], gets a [`SpawnError::Killed`] once the module has returned.
+    pub fn kill(&self) {
+        self.kill_handle().kill()
+    }
+
+    /// Get a handle for killing the process from anywhere, see [`KillHandle`]. Pipes added
+    /// after this, e.g. with [`pipe_writer`](Self::pipe_writer), aren't closed by it.
+    pub fn kill_handle(&self) -> KillHandle {
+        KillHandle {
+            stop: self.stop.clone(),
+            pipes: self.pipes.values().map(LockPipe::handle).collect(),
+            shared: self.shared.clone(),
+            paused: self.paused.clone(),
+        }
+    }
+
+    /// Have the process [killed](SpawnHandle::kill) when its [`SpawnHandle`] is dropped before
+    /// it's finished, like `tokio::process::Command::kill_on_drop`, rather than keep running on
+    /// its own. Off by default. A process that's dropped before it's spawned is always
+    /// [cancelled](#cancellation), whether or not this is set.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, so it runs for as long as stdin is open
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .kill_on_drop(true)
+    ///     .build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// drop(wasi.spawn());
+    /// // the module's pipes are closed, rather than left for it to keep using
+    /// let mut out = Vec::new();
+    /// stdout.read_to_end(&mut out).await?;
+    /// assert!(stdin.write_all(b"hello").await.is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kill_on_drop(&mut self, kill_on_drop: bool) -> &mut Self {
+        self.kill_on_drop = kill_on_drop;
+        self
+    }
+
+    /// Spawn the process and [shut it down](SpawnHandle::shutdown) right away, for a module
+    /// that's been given all of its input and only needs to see EOF to finish.
+    pub async fn shutdown(self, deadline: Duration) -> Result<ExitStatus, SpawnError> {
+        self.spawn().shutdown(deadline).await
+    }
+
     /// Spawn the process on a tokio task. It's okay to let this drop; that just means that you
     /// don't care about exactly when or how the process finishes, and you'll know you're done when
-    /// an stdio stream closes;
+    /// an stdio stream closes; see [`kill_on_drop`](Self::kill_on_drop) to stop it instead.
+    ///
+    /// Whoever has stdout and stderr has to keep reading them, or the module waits once their
+    /// buffer is full; see [`detach`](Self::detach) for a process whose output isn't wanted.
     pub fn spawn(self) -> SpawnHandle {
-        let inner = tokio::spawn(self);
-        SpawnHandle { inner }
+        self.spawn_with(tokio::spawn)
+    }
+
+    /// Put the process on a task with `spawn`, and make the handle for it.
+    fn spawn_with(
+        mut self,
+        spawn: impl FnOnce(Self) -> task::JoinHandle<<Self as Future>::Output>,
+    ) -> SpawnHandle {
+        let instance = self.instance.take();
+        let store = self.store.clone();
+        let returns = self.returns.clone();
+        let name = self.program_name.clone();
+        let kill = self.kill_handle();
+        let kill_on_drop = self.kill_on_drop;
+        let stdin = self.pipes.get(&0).map(LockPipe::handle);
+        let pause = Pause(self.paused.clone());
+        let stopped = Stopped::new(self.stop.subscribe());
+        let yields = self.yield_interval.is_some();
+        let inner = spawn(self);
+        SpawnHandle {
+            _kill_on_drop: kill_on_drop.then(|| KillOnDrop::new(kill.clone(), &inner)),
+            inner,
+            instance,
+            store,
+            returns,
+            name,
+            kill,
+            stdin,
+            pause,
+            stopped,
+            yields,
+            finished: false,
+        }
+    }
+
+    /// Spawn the process like [`spawn`](Self::spawn), but give up on it if there's no activity
+    /// on any of its stdio pipes for `idle`: nothing written to or read from stdin, stdout or
+    /// stderr. The process then fails with [`SpawnError::IdleTimeout`].
+    ///
+    /// Giving up closes all the pipes, like [dropping](#cancellation) the process, so a module
+    /// that's stuck waiting on a pipe gets EOF or a broken pipe error and can finish. A module
+    /// that's stuck in a loop without touching its stdio can't be stopped this way; use
+    /// [`new_metered`](Self::new_metered) for that.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use tokio::io::AsyncWriteExt;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// # let engine = store.engine().clone();
+    /// let idle = Duration::from_millis(100);
+    ///
+    /// // keeps getting input, so it outlives the idle timeout
+    /// let mut wasi = WasiProcessBuilder::new("echo", module.clone()).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let handle = wasi.spawn_idle_timeout(idle);
+    /// for _ in 0..10 {
+    ///     tokio::time::sleep(Duration::from_millis(20)).await;
+    ///     stdin.write_all(b"ping").await?;
+    /// }
+    /// drop(stdin);
+    /// handle.await?;
+    ///
+    /// // waits on stdin forever
+    /// # let store = wasmer::Store::new(engine);
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let _stdin = wasi.stdin.take().unwrap();
+    /// let res = wasi.spawn_idle_timeout(idle).await;
+    /// assert!(matches!(res, Err(SpawnError::IdleTimeout)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn_idle_timeout(self, idle: Duration) -> SpawnHandle {
+        let pipes = self.pipes.values().map(LockPipe::handle).collect();
+        let shared = self.shared.clone();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let paused = self.paused.clone();
+        let watchdog = tokio::spawn(watch_idle(pipes, shared, paused, idle, timed_out.clone()));
+        self.spawn_with(|process| {
+            tokio::spawn(async move {
+                let res = process.await;
+                watchdog.abort();
+                if timed_out.load(Ordering::Acquire) {
+                    Err(RuntimeError::user(Box::new(IdleTimeout)))
+                } else {
+                    res
+                }
+            })
+        })
+    }
+
+    /// Spawn the process like [`spawn`](Self::spawn), for a module whose stdio nobody cares
+    /// about. Stdin is closed, so the module reads EOF, and stdout and stderr are read and
+    /// thrown away on their own tasks, so the module can't get stuck on a full buffer. Those
+    /// tasks are stopped once the process is done. The handle only reports how it finished.
+    ///
+    /// Streams that were already taken from the process are left alone, and it's still up to
+    /// whoever has them to read them.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // writes 60,000 bytes to stdout and stderr 20 times each
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $i i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 60000))
+    ///         (loop $write
+    ///             (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (drop (call $fd_write (i32.const 2) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (local.set $i (i32.add (local.get $i) (i32.const 1)))
+    ///             (br_if $write (i32.lt_u (local.get $i) (i32.const 20))))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("chatty", module).build(store)?;
+    /// // far more than the default buffers hold, but nothing here reads it
+    /// wasi.detach().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn detach(mut self) -> SpawnHandle {
+        self.stdin = None;
+        let mut drains = Vec::new();
+        if let Some(mut stdout) = self.stdout.take() {
+            drains.push(tokio::spawn(async move {
+                let _ = io::copy(&mut stdout, &mut io::sink()).await;
+            }));
+        }
+        if let Some(mut stderr) = self.stderr.take() {
+            drains.push(tokio::spawn(async move {
+                let _ = io::copy(&mut stderr, &mut io::sink()).await;
+            }));
+        }
+        self.spawn_with(|process| {
+            tokio::spawn(async move {
+                let res = process.await;
+                for drain in drains {
+                    drain.abort();
+                }
+                res
+            })
+        })
+    }
+
+    /// Spawn the process on the current `LocalSet` with `task::spawn_local`, like
+    /// [`spawn`](Self::spawn). See [`LocalWasiProcess`] for a process whose store doesn't need
+    /// to be `Send`.
+    pub fn spawn_local(self) -> SpawnHandle {
+        self.spawn_with(task::spawn_local)
+    }
+
+    /// Give the module at most `timeout` of wall-clock time, counted from when the process starts
+    /// running, after which its [`SpawnHandle`] fails with [`SpawnError::TimedOut`] right away,
+    /// without waiting for the module to return. Running out of time also closes all the pipes,
+    /// like [killing](SpawnHandle::kill) the process, so a module that's waiting on its stdio
+    /// gets EOF or a broken pipe error and can finish.
+    ///
+    /// Like with killing, the module itself isn't interrupted, so this only bounds modules that
+    /// spend their time on stdio. One that's stuck in a loop without touching its stdio keeps
+    /// its thread busy after the deadline, until it returns; pair this with
+    /// [`new_metered`](Self::new_metered) to bound how much it can compute too.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::{Duration, Instant};
+    /// use wasi_process2::{SpawnError, TerminationReason, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// wasi.timeout(Duration::from_secs(10));
+    /// // done well within the timeout
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.stdout, b"Hello, World!\n");
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// // copies stdin to stdout until EOF, so it waits for as long as stdin is open
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module)
+    ///     .timeout(Duration::from_millis(100))
+    ///     .build(store)?;
+    /// let _stdin = wasi.stdin.take().unwrap();
+    /// let start = Instant::now();
+    /// let err = wasi.spawn().await.unwrap_err();
+    /// assert!(matches!(err, SpawnError::TimedOut));
+    /// assert_eq!(err.termination_reason(), TerminationReason::TimedOut);
+    /// assert!(start.elapsed() >= Duration::from_millis(100));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// A module that never yields still can't hold up the handle past the deadline:
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let rt = tokio::runtime::Runtime::new()?;
+    /// # rt.block_on(async {
+    /// use std::time::Duration;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (loop $spin (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("spin", module)
+    ///     .timeout(Duration::from_millis(100))
+    ///     .build(store)?;
+    /// assert!(matches!(wasi.spawn().await, Err(SpawnError::TimedOut)));
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// # })?;
+    /// // the module's still spinning on its thread, so don't wait for it
+    /// # rt.shutdown_background();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The stdio to run the module with: its ends of the pipes, which wait while it's
+    /// [paused](SpawnHandle::pause).
+    fn module_stdio(&self) -> ModuleStdio {
+        let mut pipes = Pipes::new();
+        for (&fd, pipe) in &self.pipes {
+            let mut pipe = pipe.clone_end();
+            pipe.set_paused(self.paused.subscribe());
+            pipes.insert(fd, pipe);
+        }
+        ModuleStdio {
+            pipes,
+            shared: self.shared.clone(),
+            turns: self.yield_interval.map(|interval| {
+                Turns::new(interval, self.paused.subscribe(), self.stop.subscribe())
+            }),
+        }
+    }
+
+    /// Start counting down the [`timeout`](Self::timeout), if there is one, as the process
+    /// starts running.
+    fn start_timeout(&mut self) {
+        if let Some(timeout) = self.timeout {
+            let pipes = self.pipes.values().map(LockPipe::handle).collect();
+            let shared = self.shared.clone();
+            let paused = self.paused.clone();
+            let stop = self.stop.clone();
+            let watchdog = tokio::spawn(watch_timeout(pipes, shared, paused, timeout, stop));
+            self.timeout_watchdog = Some(watchdog.abort_handle());
+        }
+    }
+
+    /// Give [`run`](Self::run) and [`wait_with_output`](Self::wait_with_output) at most
+    /// `timeout` after the module has exited to finish copying or collecting stdout and stderr,
+    /// rather than waiting for as long as it takes them to reach EOF. Whatever's been read by
+    /// then is kept, and [`Output::truncated`] says whether the rest was cut off.
+    ///
+    /// The streams of a process are closed once it's done, so this only matters when something
+    /// keeps one of them open, or when the destination of [`run`](Self::run) stops taking
+    /// output.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::{Duration, Instant};
+    /// use tokio::io::AsyncWriteExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut echo = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let mut echo_stdin = echo.stdin.take().unwrap();
+    /// echo_stdin.write_all(b"partial\n").await?;
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// // a stdout that stays open after this process exits, since echo is still waiting for EOF
+    /// wasi.stdout = echo.stdout.take();
+    /// let echo = echo.spawn();
+    /// wasi.shutdown_timeout(Duration::from_millis(100));
+    /// let start = Instant::now();
+    /// let output = wasi.wait_with_output().await?;
+    /// assert!(start.elapsed() < Duration::from_secs(10));
+    /// assert_eq!(output.status, Some(0));
+    /// assert_eq!(output.stdout, b"partial\n");
+    /// assert!(output.truncated);
+    /// drop(echo_stdin);
+    /// echo.await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.shutdown_timeout = Some(timeout);
+        self
+    }
+
+    /// Spawn the process with its stdio connected to the stdio of the host process, and wait for
+    /// it to finish, getting how it exited.
+    ///
+    /// Any of `stdin`, `stdout` or `stderr` that have already been taken are left alone. Host
+    /// stdin keeps being copied to the module until the process exits, so a module that never
+    /// reads stdin doesn't keep this from finishing. If writing to host stdout or stderr fails,
+    /// that stream is closed, and the module gets an error on its next write to it. See
+    /// [`shutdown_timeout`](Self::shutdown_timeout) to bound how long copying them can go on
+    /// after the module exits.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let mut store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// wasi.run().await?; // prints "Hello, World!"
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn run(mut self) -> Result<ExitStatus, SpawnError> {
+        let stdin = self.stdin.take();
+        let stdout = self.stdout.take();
+        let stderr = self.stderr.take();
+        let timeout = self.shutdown_timeout;
+        let handle = self.spawn();
+
+        let stdin = stdin.map(|mut stdin| {
+            tokio::spawn(async move { io::copy(&mut io::stdin(), &mut stdin).await })
+        });
+        let stdout = async {
+            if let Some(mut stdout) = stdout {
+                let _ = io::copy(&mut stdout, &mut io::stdout()).await;
+            }
+        };
+        let stderr = async {
+            if let Some(mut stderr) = stderr {
+                let _ = io::copy(&mut stderr, &mut io::stderr()).await;
+            }
+        };
+        let copy = async {
+            tokio::join!(stdout, stderr);
+        };
+        let (res, _) = wait_and_drain(handle, copy, timeout).await;
+
+        if let Some(stdin) = stdin {
+            stdin.abort();
+        }
+        res
+    }
+
+    /// Take `stdin` and spawn a task copying everything from `src` into it, closing stdin once
+    /// `src` ends, e.g. to give a one-shot run its input along with
+    /// [`wait_with_output`](Self::wait_with_output). Does nothing if `stdin` has already been
+    /// taken. This has to be called from within a tokio runtime.
+    ///
+    /// The task is aborted once the process is done, so it doesn't hang around if the module
+    /// never reads all of its input, or if `src` never ends.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::io::Cursor;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// # let engine = store.engine().clone();
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// wasi.feed_stdin(Cursor::new(b"hello".to_vec()));
+    /// assert!(wasi.stdin.is_none());
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.stdout, b"helloEND\n");
+    ///
+    /// // never reads stdin, and never gets EOF from it either
+    /// # let store = wasmer::Store::new(engine);
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// wasi.feed_stdin(tokio::io::repeat(b'a'));
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.stdout, b"Hello, World!\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn feed_stdin(&mut self, mut src: impl AsyncRead + Send + Unpin + 'static) {
+        if let Some(mut stdin) = self.stdin.take() {
+            let feed = tokio::spawn(async move {
+                if io::copy(&mut src, &mut stdin).await.is_ok() {
+                    let _ = stdin.shutdown().await;
+                }
+            });
+            self.stdin_feed = Some(feed.abort_handle());
+        }
+    }
+
+    /// Close stdin, so the module reads EOF once it's read whatever was already written to it,
+    /// and set `stdin` to `None`. Does nothing if `stdin` has already been taken, so it's fine to
+    /// call more than once; [`feed_stdin`](Self::feed_stdin) closes it by itself once its input
+    /// ends.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncWriteExt;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// wasi.stdin.as_mut().unwrap().write_all(b"hello").await?;
+    /// wasi.shutdown_stdin();
+    /// wasi.shutdown_stdin();
+    /// assert!(wasi.stdin.is_none());
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.stdout, b"helloEND\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shutdown_stdin(&mut self) {
+        // dropping the host end is what closes it
+        self.stdin = None;
+    }
+
+    /// Spawn the process and hold a conversation with it, for a module that answers each input
+    /// with a response: each chunk of `inputs` is written to stdin, and then what the module
+    /// writes to stdout is returned as its response before the next one is written. This has to
+    /// be called from within a tokio runtime.
+    ///
+    /// A response is whatever the module has written by the time there's something to read,
+    /// or, with a [`delimiter`](Conversation::delimiter), everything up to and including it.
+    /// Once `inputs` ends, stdin is closed, and anything the module writes after that still
+    /// comes out as responses. If the module exits partway through, the inputs it didn't read
+    /// are dropped, and the conversation ends once its remaining output has been returned; see
+    /// [`Conversation::wait`] for how it exited.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use bytes::Bytes;
+    /// use tokio_stream::StreamExt;
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let inputs = ["hello\n", "how are you?\n", "bye\n"].map(Bytes::from);
+    /// let mut conversation = wasi.converse(tokio_stream::iter(inputs.clone()));
+    /// conversation.delimiter(b'\n');
+    /// let responses: Vec<_> = (&mut conversation).take(3).collect().await;
+    /// assert_eq!(responses, inputs);
+    /// assert_eq!(conversation.next_response().await.unwrap(), "END\n");
+    /// assert_eq!(conversation.next_response().await, None);
+    /// assert_eq!(conversation.wait().await?, 0);
+    ///
+    /// // echoes the first thing it reads, and exits
+    /// let once = r#"(module
+    ///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start")
+    ///         (i32.store (i32.const 0) (i32.const 64))
+    ///         (i32.store (i32.const 4) (i32.const 1024))
+    ///         (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///         (i32.store (i32.const 4) (i32.load (i32.const 8)))
+    ///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+    /// # let store = wasmer::Store::new(engine);
+    /// let module = wasmer::Module::new(&store, once)?;
+    /// let wasi = WasiProcessBuilder::new("once", module).build(store)?;
+    /// let mut conversation = wasi.converse(tokio_stream::iter(inputs));
+    /// assert_eq!(conversation.next_response().await.unwrap(), "hello\n");
+    /// assert_eq!(conversation.next_response().await, None);
+    /// assert_eq!(conversation.wait().await?, 0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn converse<S>(self, inputs: S) -> Conversation<S>
+    where
+        S: futures_core::Stream<Item = bytes::Bytes> + Unpin,
+    {
+        Conversation::new(self, inputs)
+    }
+
+    /// Drive the process and poll all of its streams at once, for an event loop that doesn't use
+    /// `select!`. This is ready when any of them is, with everything that's ready in the
+    /// [`IoEvent`]; otherwise the current task is woken once something changes.
+    ///
+    /// Only streams that are still in `stdin`, `stdout` and `stderr` are polled. Stdin is
+    /// writable whenever there's room in its buffer, which is most of the time, so take it or
+    /// [`shutdown_stdin`](Self::shutdown_stdin) once there's nothing more to write, or this is
+    /// always ready. The exit is reported once, and only once there's nothing left to read from
+    /// stdout and stderr, so that output isn't missed. After that, the process can't be polled or
+    /// spawned anymore.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::future::poll_fn;
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let mut wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// wasi.shutdown_stdin();
+    /// let event = poll_fn(|cx| wasi.poll_io(cx)).await;
+    /// assert!(event.stdout_readable);
+    /// assert!(event.exited.is_none());
+    /// let out = wasi.stdout.as_ref().unwrap().drain_remaining();
+    /// assert_eq!(out, "Hello, World!\n");
+    /// let event = poll_fn(|cx| wasi.poll_io(cx)).await;
+    /// assert!(!event.stdout_readable);
+    /// assert!(matches!(event.exited, Some(Ok(status)) if status.success()));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn poll_io(&mut self, cx: &mut Context<'_>) -> Poll<IoEvent> {
+        if self.io_exit.is_none() {
+            if let Poll::Ready(res) = Pin::new(&mut *self).poll(cx) {
+                self.io_exit = Some(Some(res));
+            }
+        }
+        let stdin = self.stdin.as_ref().map(|stdin| &stdin.inner);
+        let stdout = self.stdout.as_ref().map(|stdout| &stdout.inner);
+        let stderr = self.stderr.as_ref().map(|stderr| &stderr.inner);
+        let mut event = IoEvent {
+            stdin_writable: stdin.is_some_and(|pipe| pipe.poll_capacity(cx).is_ready()),
+            stdout_readable: stdout.is_some_and(|pipe| pipe.poll_has_data(cx).is_ready()),
+            stderr_readable: stderr.is_some_and(|pipe| pipe.poll_has_data(cx).is_ready()),
+            exited: None,
+        };
+        if !event.stdout_readable && !event.stderr_readable {
+            event.exited = self.io_exit.as_mut().and_then(Option::take);
+        }
+        let ready = event.stdin_writable
+            || event.stdout_readable
+            || event.stderr_readable
+            || event.exited.is_some();
+        if ready {
+            Poll::Ready(event)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Spawn the process, collect everything it writes to stdout and stderr, and wait for it to
+    /// finish, like `std::process::Child::wait_with_output`.
+    ///
+    /// Stdin is closed before the process starts, if it hasn't been taken. Both streams are read
+    /// concurrently, so a module filling one of them can't stall while the other is being read.
+    /// If the module traps, or the process is stopped, e.g. by a [`timeout`](Self::timeout), the
+    /// output it produced up to that point is still returned, with a `status` of `None` and the
+    /// reason in `termination`.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use wasi_process2::{TerminationReason, WasiProcessBuilder};
+    /// # let mut store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+    /// let wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.status, Some(0));
+    /// assert_eq!(output.termination, TerminationReason::Exited(0));
+    /// assert_eq!(output.stdout, b"Hello, World!\n");
+    /// assert!(output.stderr.is_empty());
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// // writes to stdout until that fails, then exits with the errno
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $errno i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (loop $write
+    ///             (local.set $errno
+    ///                 (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (br_if $write (i32.eqz (local.get $errno))))
+    ///         (call $proc_exit (local.get $errno)))
+    ///     (func (param i64)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("yes", module)
+    ///     .timeout(Duration::from_millis(100))
+    ///     .build(store)?;
+    /// let output = wasi.wait_with_output().await?;
+    /// assert_eq!(output.status, None);
+    /// assert_eq!(output.termination, TerminationReason::TimedOut);
+    /// assert!(!output.stdout.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn wait_with_output(mut self) -> Result<Output, SpawnError> {
+        drop(self.stdin.take());
+        let stdout = self.stdout.take();
+        let stderr = self.stderr.take();
+        let timeout = self.shutdown_timeout;
+        let handle = self.spawn();
+
+        let mut stdout_buf = Vec::new();
+        let mut stderr_buf = Vec::new();
+        let collect = async {
+            tokio::join!(
+                read_all(stdout, &mut stdout_buf),
+                read_all(stderr, &mut stderr_buf)
+            );
+        };
+        let (res, truncated) = wait_and_drain(handle, collect, timeout).await;
+
+        let (status, termination) = match res {
+            Ok(status) => (
+                Some(status.code()),
+                TerminationReason::Exited(status.code()),
+            ),
+            Err(e @ SpawnError::Join(_)) => return Err(e),
+            Err(e) => (None, e.termination_reason()),
+        };
+        Ok(Output {
+            status,
+            termination,
+            stdout: stdout_buf,
+            stderr: stderr_buf,
+            truncated,
+        })
+    }
+}
+
+/// Wait for `process` while running `drain`, and then give `drain` at most `timeout` to finish,
+/// if there is one. Returns the result of the process, and whether `drain` was cut off.
+async fn wait_and_drain<R>(
+    process: impl Future<Output = R>,
+    drain: impl Future<Output = ()>,
+    timeout: Option<Duration>,
+) -> (R, bool) {
+    tokio::pin!(process);
+    tokio::pin!(drain);
+    let res = tokio::select! {
+        res = &mut process => res,
+        () = &mut drain => return (process.await, false),
+    };
+    match timeout {
+        Some(timeout) => (res, tokio::time::timeout(timeout, drain).await.is_err()),
+        None => {
+            drain.await;
+            (res, false)
+        }
+    }
+}
+
+/// Read everything from `stream` into `buf`, if there's a stream. What's been read stays in
+/// `buf` even if this is cancelled partway through.
+async fn read_all(stream: Option<impl AsyncRead + Unpin>, buf: &mut Vec<u8>) {
+    if let Some(mut stream) = stream {
+        while let Ok(n) = stream.read_buf(buf).await {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Run the `_start` function of a wasi instance to completion without needing a tokio runtime,
+/// feeding it `input` on stdin and returning how it exited along with everything it wrote to
+/// stdout and stderr.
+///
+/// The instance should've been set up with [`add_stdio`]. This builds a current-thread runtime
+/// internally and runs the module like [`WasiProcess::new_blocking`], so it mustn't be called from
+/// within an async context. A missing `_start` export, a runtime that can't be built or a trap
+/// is returned as a [`RunSyncError`], but a `proc_exit` with a nonzero code isn't: check the
+/// [`ExitStatus`], like with `std::process::Command::output`.
+///
+/// # Examples
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use wasmer_wasi::WasiState;
+/// # let mut store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// # let mut state = WasiState::new("echo");
+/// # wasi_process2::add_stdio(&mut state);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let (status, stdout, stderr) = wasi_process2::run_sync(store, &instance, b"hello\n")?;
+/// assert!(status.success());
+/// assert_eq!(stdout, b"hello\nEND\n");
+/// assert!(stderr.is_empty());
+///
+/// # let mut store = wasmer::Store::new(engine);
+/// let wat = r#"(module
+///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start") (call $proc_exit (i32.const 3))))"#;
+/// # let module = wasmer::Module::new(&store, wat)?;
+/// # let mut state = WasiState::new("exit");
+/// # wasi_process2::add_stdio(&mut state);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let (status, _, _) = wasi_process2::run_sync(store, &instance, b"")?;
+/// assert_eq!(status.code(), 3);
+/// # Ok(())
+/// # }
+/// ```
+pub fn run_sync(
+    store: impl AsStoreMut + Send + Sync + 'static,
+    instance: &wasmer::Instance,
+    input: &[u8],
+) -> Result<(ExitStatus, Vec<u8>, Vec<u8>), RunSyncError> {
+    let mut wasi = WasiProcess::new_blocking(store, instance, MaxBufSize::default())?;
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .map_err(RunSyncError::Runtime)?;
+    rt.block_on(async move {
+        let stdin = wasi.stdin.take();
+        let stdout = wasi.stdout.take();
+        let stderr = wasi.stderr.take();
+        let handle = wasi.spawn();
+
+        let stdin = async {
+            if let Some(mut stdin) = stdin {
+                // the module may exit without reading all of its input
+                let _ = stdin.write_all(input).await;
+                let _ = stdin.shutdown().await;
+            }
+        };
+        let stdout = async {
+            let mut buf = Vec::new();
+            if let Some(mut stdout) = stdout {
+                let _ = stdout.read_to_end(&mut buf).await;
+            }
+            buf
+        };
+        let stderr = async {
+            let mut buf = Vec::new();
+            if let Some(mut stderr) = stderr {
+                let _ = stderr.read_to_end(&mut buf).await;
+            }
+            buf
+        };
+        let (res, (), stdout, stderr) = tokio::join!(handle, stdin, stdout, stderr);
+        Ok((res?, stdout, stderr))
+    })
+}
+
+/// Get the exported function `name` to use as the entry point of a process.
+fn get_start(instance: &wasmer::Instance, name: &str) -> Result<wasmer::Function, NewError> {
+    match instance.exports.get_function(name) {
+        Ok(start) => Ok(start.clone()),
+        Err(wasmer::ExportError::Missing(_)) => Err(NewError::MissingStart(name.to_owned())),
+        Err(e) => Err(NewError::Export(e)),
+    }
+}
+
+/// Check that `start` can be used as the entry point of a process, i.e. that it's `() -> ()`.
+fn check_start(store: &impl AsStoreRef, start: &wasmer::Function) -> Result<(), NewError> {
+    let expected = wasmer::FunctionType::new([], []);
+    let found = start.ty(store);
+    if found != expected {
+        return Err(NewError::WrongSignature { expected, found });
+    }
+    Ok(())
+}
+
+/// An error creating a [`WasiProcess`] from an instance.
+///
+/// # Examples
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use wasmer_wasi::WasiState;
+/// use wasi_process2::{MaxBufSize, NewError, WasiProcess};
+/// let instantiate = |wat: &str| -> Result<_, Box<dyn std::error::Error>> {
+///     let mut store = wasmer::Store::default();
+///     let module = wasmer::Module::new(&store, wat)?;
+///     # let mut state = WasiState::new("progg");
+///     # wasi_process2::add_stdio(&mut state);
+///     # let mut env = state.finalize(&mut store)?;
+///     # let imports = env.import_object(&mut store, &module)?;
+///     # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+///     # env.initialize(&mut store, &instance)?;
+///     Ok((store, instance))
+/// };
+/// const IMPORTS: &str = r#"
+///     (import "wasi_unstable" "fd_write" (func (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)"#;
+///
+/// let missing = instantiate(&format!(r#"(module {} (func (export "main")))"#, IMPORTS))?;
+/// let mistyped = instantiate(&format!(
+///     r#"(module {} (func (export "_start") (param i32)))"#,
+///     IMPORTS
+/// ))?;
+///
+/// let err = WasiProcess::new(missing.0, &missing.1, MaxBufSize::default()).err().unwrap();
+/// assert!(matches!(err, NewError::MissingStart(name) if name == "_start"));
+///
+/// let err = WasiProcess::new(mistyped.0, &mistyped.1, MaxBufSize::default()).err().unwrap();
+/// assert!(matches!(
+///     err,
+///     NewError::WrongSignature { found, .. } if found.params() == [wasmer::Type::I32]
+/// ));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum NewError {
+    /// The instance doesn't export the entry point function, `_start` unless another one was
+    /// given
+    MissingStart(String),
+    /// The entry point function doesn't have the `() -> ()` signature
+    WrongSignature {
+        /// The signature the entry point needs to have
+        expected: wasmer::FunctionType,
+        /// The actual signature of the entry point
+        found: wasmer::FunctionType,
+    },
+    /// Another error getting an export, e.g. if the entry point isn't a function, or the
+    /// metering globals for [`WasiProcess::new_metered`] are missing
+    Export(wasmer::ExportError),
+    /// A [`MaxBufSize`] field is 0, or a stream is given a buffer size of 0 later, which would
+    /// block every write to that stream forever. This holds the name of the stream, e.g.
+    /// `"stdout"`
+    ZeroBufSize(&'static str),
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingStart(name) => write!(f, "missing entry point export `{}`", name),
+            Self::WrongSignature { expected, found } => write!(
+                f,
+                "the entry point has the signature {}, but it should be {}",
+                found, expected
+            ),
+            Self::Export(e) => write!(f, "export error: {}", e),
+            Self::ZeroBufSize(stream) => write!(f, "the max buffer size for {} is 0", stream),
+        }
+    }
+}
+
+impl std::error::Error for NewError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Export(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wasmer::ExportError> for NewError {
+    fn from(e: wasmer::ExportError) -> Self {
+        Self::Export(e)
+    }
+}
+
+/// An error from [`run_sync`], which can fail before the process even starts.
+///
+/// # Examples
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use wasi_process2::{NewError, RunSyncError};
+/// # use wasmer_wasi::WasiState;
+/// let wat = r#"(module
+///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+///     (memory (export "memory") 1)
+///     (func (export "main")))"#;
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, wat)?;
+/// # let mut state = WasiState::new("main");
+/// # wasi_process2::add_stdio(&mut state);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let err = wasi_process2::run_sync(store, &instance, b"").unwrap_err();
+/// assert!(matches!(err, RunSyncError::New(NewError::MissingStart(_))));
+/// assert_eq!(err.to_string(), "missing entry point export `_start`");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub enum RunSyncError {
+    /// The process couldn't be created from the instance, see [`WasiProcess::new_blocking`]
+    New(NewError),
+    /// The runtime to run the process on couldn't be built
+    Runtime(io::Error),
+    /// The process failed
+    Spawn(SpawnError),
+}
+
+impl fmt::Display for RunSyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::New(e) => write!(f, "{}", e),
+            Self::Runtime(e) => write!(f, "couldn't build the tokio runtime: {}", e),
+            Self::Spawn(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RunSyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::New(e) => e.source(),
+            Self::Runtime(e) => Some(e),
+            Self::Spawn(e) => e.source(),
+        }
+    }
+}
+
+impl From<NewError> for RunSyncError {
+    fn from(e: NewError) -> Self {
+        Self::New(e)
+    }
+}
+
+impl From<SpawnError> for RunSyncError {
+    fn from(e: SpawnError) -> Self {
+        Self::Spawn(e)
+    }
+}
+
+/// The output of a finished process, returned by [`WasiProcess::wait_with_output`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    /// The exit code of the process: 0 if `_start` returned, the code passed to `proc_exit`, or
+    /// `None` if it didn't exit, e.g. because the module trapped or the process timed out
+    pub status: Option<i32>,
+    /// Why the process stopped, which tells apart the ways it can end without a `status`
+    pub termination: TerminationReason,
+    /// Everything the process wrote to stdout
+    pub stdout: Vec<u8>,
+    /// Everything the process wrote to stderr
+    pub stderr: Vec<u8>,
+    /// Whether collecting stdout and stderr was cut off by
+    /// [`WasiProcess::shutdown_timeout`], so they might be missing the end
+    pub truncated: bool,
+}
+
+/// What's ready on a process, from [`WasiProcess::poll_io`].
+#[derive(Debug)]
+pub struct IoEvent {
+    /// Stdin has room to write to, or is closed, so the next write returns the error
+    pub stdin_writable: bool,
+    /// Stdout has something buffered to read
+    pub stdout_readable: bool,
+    /// Stderr has something buffered to read
+    pub stderr_readable: bool,
+    /// The result of the process, like awaiting it would return, once it's finished and
+    /// there's nothing left to read from stdout and stderr
+    pub exited: Option<Result<ExitStatus, RuntimeError>>,
+}
+
+/// The result of [`WasiStdout::read_eof_aware`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadStatus {
+    /// This many bytes were read, which is only 0 if the buffer was empty
+    Data(usize),
+    /// Stdout is closed, and everything in it has been read
+    Eof,
+}
+
+/// How a module exited, when it did so cleanly: either `_start` returned, which is exit code 0,
+/// or the module called `proc_exit`. Awaiting a process gets this even for a nonzero code, like
+/// `std::process::ExitStatus`, and only fails for traps and errors on the host's side.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use wasi_process2::WasiProcessBuilder;
+/// # let store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// let wat = r#"(module
+///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start") (call $proc_exit (i32.const 3))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let wasi = WasiProcessBuilder::new("exit", module).build(store)?;
+/// let status = wasi.spawn().await?;
+/// assert_eq!(status.code(), 3);
+/// assert!(!status.success());
+/// assert_eq!(status.to_string(), "exit status: 3");
+///
+/// # let store = wasmer::Store::new(engine);
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// let wasi = WasiProcessBuilder::new("hello", module).build(store)?;
+/// assert!(wasi.spawn().await?.success());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+    /// The exit code: what the module passed to `proc_exit`, or 0 if `_start` returned.
+    pub fn code(&self) -> i32 {
+        self.0
+    }
+
+    /// Whether the exit code is 0.
+    pub fn success(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit status: {}", self.0)
+    }
+}
+
+/// Get the exit code from the result of running a process: 0 if `_start` returned, or the code
+/// passed to `proc_exit`. Any other error is returned as-is.
+fn exit_code(res: Result<(), RuntimeError>) -> Result<i32, RuntimeError> {
+    match res {
+        Ok(()) => Ok(0),
+        Err(e) => match e.downcast::<WasiError>() {
+            Ok(WasiError::Exit(code)) => Ok(code as i32),
+            Ok(e) => Err(RuntimeError::user(Box::new(e))),
+            Err(e) => Err(e),
+        },
     }
 }
 
 impl Future for WasiProcess {
-    type Output = Result<(), RuntimeError>;
+    type Output = Result<ExitStatus, RuntimeError>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        self.handle.as_mut().poll(cx)
+        let this = &mut *self;
+        let handle = match &mut this.handle {
+            Some(handle) => handle,
+            None => {
+                let start = this.start.take().expect("the start fn is only taken once");
+                this.start_timeout();
+                let process = start(this.module_stdio());
+                #[cfg(feature = "tracing")]
+                let process = Box::pin(traced(process, this.program_name.as_deref()));
+                this.handle.insert(process)
+            }
+        };
+        let res = ready!(handle.as_mut().poll(cx));
+        Poll::Ready(this.finish(res))
+    }
+}
+
+/// Run `process` in a span for the program, with events for when it starts and finishes.
+#[cfg(feature = "tracing")]
+fn traced<F>(process: F, program_name: Option<&str>) -> impl Future<Output = F::Output>
+where
+    F: Future<Output = Result<(), RuntimeError>>,
+{
+    use tracing::Instrument;
+    let span = tracing::info_span!("wasi_process", program = program_name);
+    async move {
+        tracing::info!("process started");
+        let res = process.await;
+        match &res {
+            Ok(()) => tracing::info!("process finished"),
+            Err(e) => tracing::info!(error = %e, "process finished"),
+        }
+        res
+    }
+    .instrument(span)
+}
+
+impl Drop for WasiProcess {
+    fn drop(&mut self) {
+        // see the "Cancellation" section of the docs
+        close_all(self.pipes.values(), &self.shared, &self.paused);
+        if let Some(feed) = self.stdin_feed.take() {
+            feed.abort();
+        }
+        if let Some(watchdog) = self.timeout_watchdog.take() {
+            watchdog.abort();
+        }
     }
 }
 
@@ -211,25 +4293,1004 @@ impl Future for WasiProcess {
 #[derive(Debug)]
 pub struct SpawnHandle {
     inner: tokio::task::JoinHandle<<WasiProcess as Future>::Output>,
+    instance: Option<wasmer::Instance>,
+    store: StoreSlot,
+    returns: ReturnSlot,
+    name: Option<String>,
+    kill: KillHandle,
+    /// Kills the process when the handle's dropped, if it's set to be; only held to be dropped.
+    _kill_on_drop: Option<KillOnDrop>,
+    /// The module's end of stdin, for [`shutdown`](Self::shutdown).
+    stdin: Option<LockPipe>,
+    pause: Pause,
+    stopped: Stopped,
+    /// Whether the module [yields](WasiProcessBuilder::yield_interval), so it can be paused
+    /// without any pipes.
+    yields: bool,
+    /// Whether the result has already been taken from `inner`, which can't be polled again.
+    finished: bool,
+}
+
+impl SpawnHandle {
+    /// The instance the process was created from, or `None` if it was created from just a
+    /// function, with [`WasiProcess::with_function`],
+    /// [`with_function_args`](WasiProcess::with_function_args) or
+    /// [`with_function_blocking`](WasiProcess::with_function_blocking).
+    pub fn instance(&self) -> Option<&wasmer::Instance> {
+        self.instance.as_ref()
+    }
+
+    /// Get a handle for aborting the process's task, which can be used from anywhere while the
+    /// `SpawnHandle` itself is being awaited. Whatever awaits the `SpawnHandle` then gets a
+    /// [`SpawnError::Join`] that's cancelled.
+    ///
+    /// The task can only be aborted where it waits, which a process from [`WasiProcess::new`]
+    /// doesn't do until the module returns, since the module runs right inside it; closing the
+    /// process's stdio as well makes a module that's waiting on it finish. One from
+    /// [`WasiProcess::new_blocking`] is aborted right away, but the module keeps going on its
+    /// blocking thread until it returns.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasmer_wasi::WasiState;
+    /// use wasi_process2::{MaxBufSize, SpawnError, WasiProcess};
+    /// # let mut store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, so it waits for as long as stdin is open
+    /// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// # let mut state = WasiState::new("echo");
+    /// # wasi_process2::add_stdio(&mut state);
+    /// # let mut env = state.finalize(&mut store)?;
+    /// # let imports = env.import_object(&mut store, &module)?;
+    /// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+    /// # env.initialize(&mut store, &instance)?;
+    /// let mut wasi = WasiProcess::new_blocking(store, &instance, MaxBufSize::default())?;
+    /// let stdin = wasi.stdin.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let abort = handle.abort_handle();
+    /// let waiter = tokio::spawn(handle);
+    /// tokio::spawn(async move { abort.abort() });
+    /// let res = waiter.await?;
+    /// assert!(matches!(res, Err(SpawnError::Join(e)) if e.is_cancelled()));
+    /// // the module is still waiting on its blocking thread, so let it finish
+    /// drop(stdin);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn abort_handle(&self) -> task::AbortHandle {
+        self.inner.abort_handle()
+    }
+
+    /// Kill the process: the handle fails with [`SpawnError::Killed`] right away, without
+    /// waiting for the module to return, and all of the process's pipes are closed, like when
+    /// it's [dropped](WasiProcess#cancellation), so a module that's waiting on its stdio gets
+    /// EOF or a broken pipe error and can finish. That includes any pipe the module was given
+    /// with [`add_stdio_with_pipes`] once it's used it. Killing a process that's already
+    /// finished does nothing.
+    ///
+    /// The module itself isn't interrupted, since wasm can't be stopped from the outside while
+    /// it runs on this version of wasmer. One that's stuck in a loop without touching its stdio
+    /// keeps going on its thread, a runtime worker for [`WasiProcess::new`] or a blocking thread
+    /// for [`WasiProcess::new_blocking`], until it returns; use [`WasiProcess::new_metered`] to
+    /// bound that. See [`kill_handle`](Self::kill_handle) to kill the process while the handle is
+    /// being awaited.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{SpawnError, TerminationReason, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, so it waits for as long as stdin is open
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let _stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// handle.kill();
+    /// let err = handle.await.unwrap_err();
+    /// assert!(matches!(err, SpawnError::Killed));
+    /// assert_eq!(err.termination_reason(), TerminationReason::Killed);
+    /// // stdout was closed along with the rest, so the module couldn't write "END\n"
+    /// let mut out = Vec::new();
+    /// stdout.read_to_end(&mut out).await?;
+    /// assert!(out.is_empty());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// A module that never yields still can't hold up the handle:
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let rt = tokio::runtime::Runtime::new()?;
+    /// # rt.block_on(async {
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (loop $spin (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let handle = WasiProcessBuilder::new("spin", module).build(store)?.spawn();
+    /// handle.kill();
+    /// assert!(matches!(handle.await, Err(SpawnError::Killed)));
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// # })?;
+    /// // the module's still spinning on its thread, so don't wait for it
+    /// # rt.shutdown_background();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kill(&self) {
+        if !self.finished && !self.inner.is_finished() {
+            self.kill.kill();
+        }
+    }
+
+    /// Get a handle for killing the process, which can be used from anywhere while the
+    /// `SpawnHandle` itself is being awaited, see [`kill`](Self::kill).
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // copies stdin to stdout until EOF, so it waits for as long as stdin is open
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let _stdin = wasi.stdin.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// let kill = handle.kill_handle();
+    /// tokio::spawn(async move {
+    ///     tokio::time::sleep(Duration::from_millis(50)).await;
+    ///     kill.kill();
+    /// });
+    /// assert!(matches!(handle.await, Err(SpawnError::Killed)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kill_handle(&self) -> KillHandle {
+        self.kill.clone()
+    }
+
+    /// Pause the process, e.g. for scheduling modules a turn at a time: the module waits at its
+    /// next stdio call, or [yield](WasiProcessBuilder::yield_interval), until it's
+    /// [resumed](Self::resume), with its instance and pipes left as they are. Returns whether the
+    /// pause can take effect: `false` if the process has already finished or been
+    /// [killed](Self::kill) or timed out, or if it has no pipes left open for the module to stop
+    /// at and doesn't yield, in which case this does nothing. Pausing a process that's already
+    /// paused keeps it paused.
+    ///
+    /// Like with [`kill`](Self::kill), wasm can't be stopped from the outside while it runs, so
+    /// a module only pauses once it touches its stdio or yields; one that's computing without
+    /// either keeps going until it does, which for a compute-bound module might be never, even
+    /// when it's [metered](WasiProcess::new_metered), since running out of fuel can only end it.
+    /// A call that's already waiting on a pipe finishes first. The process can still be killed
+    /// while it's paused, and it's resumed once the handle is dropped. A [`WasiProcess::timeout`] keeps counting down while it's paused, and a
+    /// [`spawn_idle_timeout`](WasiProcess::spawn_idle_timeout) gives up on it if it's paused for
+    /// longer than that.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use tokio::io::AsyncReadExt;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// // writes to stdout until that fails, then exits with the errno
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $errno i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (loop $write
+    ///             (local.set $errno
+    ///                 (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (br_if $write (i32.eqz (local.get $errno))))
+    ///         (call $proc_exit (local.get $errno)))
+    ///     (func (param i64)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("yes", module).build(store)?;
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// let handle = wasi.spawn();
+    /// assert!(handle.pause());
+    /// let mut buf = vec![0; 1 << 20];
+    /// let wait = Duration::from_millis(100);
+    /// // a write that was already waiting for room still goes through once there's some
+    /// let _ = tokio::time::timeout(wait, stdout.read(&mut buf)).await;
+    /// let _ = tokio::time::timeout(wait, stdout.read(&mut buf)).await;
+    /// // but after that, the module doesn't write any more, even with all the room it needs
+    /// assert!(tokio::time::timeout(wait, stdout.read(&mut buf)).await.is_err());
+    ///
+    /// let written = stdout.metrics().bytes_written;
+    /// handle.resume();
+    /// while stdout.metrics().bytes_written <= written {
+    ///     stdout.read(&mut buf).await?;
+    /// }
+    /// handle.kill();
+    /// // there's nothing left for it to pause at
+    /// assert!(!handle.pause());
+    /// assert!(matches!(handle.await, Err(SpawnError::Killed)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn pause(&self) -> bool {
+        let open = |pipe: &LockPipe| !pipe.is_fully_closed();
+        let can_pause = !self.finished
+            && !self.inner.is_finished()
+            && self.kill.stop.borrow().is_none()
+            && (self.yields
+                || self.kill.pipes.iter().any(open)
+                || self.kill.shared.handles().iter().any(open));
+        if can_pause {
+            self.pause.0.send_replace(true);
+        }
+        can_pause
+    }
+
+    /// Resume the process after it's been [paused](Self::pause), letting the module carry on
+    /// from the stdio call it's waiting at. Resuming a process that isn't paused does nothing.
+    pub fn resume(&self) {
+        self.pause.0.send_replace(false);
+    }
+
+    /// Ask the process to finish, and make it if it doesn't: stdin is closed, so the module
+    /// reads whatever's left in it and then EOF, and if it hasn't exited after `deadline`, it's
+    /// [killed](Self::kill) and fails with [`SpawnError::Killed`]. Whoever has stdin can't
+    /// write to it anymore either.
+    ///
+    /// This never waits past the deadline, but like with `kill`, a module that's stuck in a loop
+    /// without touching its stdio keeps its thread busy until it returns.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use wasi_process2::{MaxBufSize, SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// // copies stdin to stdout until EOF, then writes "END\n"
+    /// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+    /// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+    /// let mut stdin = wasi.stdin.take().unwrap();
+    /// let mut stdout = wasi.stdout.take().unwrap();
+    /// stdin.write_all(b"bye").await?;
+    /// let handle = wasi.spawn();
+    /// let status = handle.shutdown(Duration::from_secs(10)).await?;
+    /// assert!(status.success());
+    /// let mut out = String::new();
+    /// stdout.read_to_string(&mut out).await?;
+    /// assert_eq!(out, "byeEND\n");
+    ///
+    /// # let store = wasmer::Store::new(engine);
+    /// // writes to stdout until that fails, then exits with the errno
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (local $errno i32)
+    ///         (i32.store (i32.const 0) (i32.const 16))
+    ///         (i32.store (i32.const 4) (i32.const 16))
+    ///         (loop $write
+    ///             (local.set $errno
+    ///                 (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+    ///             (br_if $write (i32.eqz (local.get $errno))))
+    ///         (call $proc_exit (local.get $errno)))
+    ///     (func (param i64)))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut wasi = WasiProcessBuilder::new("yes", module)
+    ///     .max_buf_size(MaxBufSize { stdout: 64, ..MaxBufSize::default() })
+    ///     .build(store)?;
+    /// // nothing reads stdout, so the module's stuck on a full buffer and never sees EOF
+    /// let _stdout = wasi.stdout.take().unwrap();
+    /// let res = wasi.shutdown(Duration::from_millis(100)).await;
+    /// assert!(matches!(res, Err(SpawnError::Killed)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Nor can a module that never yields:
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let rt = tokio::runtime::Runtime::new()?;
+    /// # rt.block_on(async {
+    /// use std::time::Duration;
+    /// use wasi_process2::{SpawnError, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (loop $spin (br $spin))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let handle = WasiProcessBuilder::new("spin", module).build(store)?.spawn();
+    /// let res = handle.shutdown(Duration::from_millis(100)).await;
+    /// assert!(matches!(res, Err(SpawnError::Killed)));
+    /// # Ok::<_, Box<dyn std::error::Error>>(())
+    /// # })?;
+    /// // the module's still spinning on its thread, so don't wait for it
+    /// # rt.shutdown_background();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn shutdown(mut self, deadline: Duration) -> Result<ExitStatus, SpawnError> {
+        if let Some(stdin) = &self.stdin {
+            stdin.close_write();
+        }
+        match tokio::time::timeout(deadline, &mut self).await {
+            Ok(res) => res,
+            Err(_) => {
+                self.kill();
+                // a module that's still running might never return, so don't wait for it
+                self.try_wait().unwrap_or(Err(SpawnError::Killed))
+            }
+        }
+    }
+
+    /// The name of the process, see [`WasiProcess::name`].
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") unreachable))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("crashy", module).build(store)?;
+    /// let mut handle = wasi.spawn();
+    /// assert_eq!(handle.name(), Some("crashy"));
+    /// let err = (&mut handle).await.unwrap_err();
+    /// let msg = err.with_name(handle.name()).to_string();
+    /// assert!(msg.starts_with("crashy: runtime wasi/wasm error"), "{}", msg);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Run `f` with the store and instance of a finished process, e.g. to read a result out of
+    /// the module's memory. Returns `None` if the module hasn't returned yet or panicked, or if
+    /// the process wasn't created from an instance.
+    ///
+    /// The store is moved into the process while it runs, and is only put back once the module
+    /// has returned, so this can never touch wasmer state the module is using. It's behind a
+    /// lock, so calls from different threads wait for each other.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (i32.store8 (i32.const 100) (i32.const 42))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let wasi = WasiProcessBuilder::new("progg", module).build(store)?;
+    /// let mut handle = wasi.spawn();
+    /// (&mut handle).await?;
+    /// let value = handle.inspect(|store, instance| {
+    ///     let memory = instance.exports.get_memory("memory").unwrap();
+    ///     memory.view(store).read_u8(100)
+    /// });
+    /// assert_eq!(value.unwrap()?, 42);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn inspect<R>(
+        &self,
+        f: impl FnOnce(&mut wasmer::StoreMut, &wasmer::Instance) -> R,
+    ) -> Option<R> {
+        let instance = self.instance.as_ref()?;
+        let mut store = self.store.0.lock();
+        let store = store.as_mut()?;
+        Some(f(&mut store.as_store_mut(), instance))
+    }
+
+    /// What the function of a process created with [`WasiProcess::with_function_args`]
+    /// returned, once it has. Returns `None` before that, if it failed, or if the process was
+    /// created some other way.
+    pub fn return_values(&self) -> Option<Box<[wasmer::Value]>> {
+        self.returns.lock().clone()
+    }
+
+    /// Check whether the process has finished without waiting for it, like
+    /// [`try_wait`](Self::try_wait) but with just the exit code, and `Ready(Ok(None))` once the
+    /// result has been taken.
+    #[deprecated(note = "use `try_wait` instead, which gets the whole `ExitStatus`")]
+    pub fn try_status(&mut self) -> Poll<Result<Option<i32>, SpawnError>> {
+        if self.finished {
+            return Poll::Ready(Ok(None));
+        }
+        match self.try_wait() {
+            Some(res) => Poll::Ready(res.map(|status| Some(status.code()))),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Get how the process exited if it's finished, or `None` right away if it hasn't, like
+    /// `std::process::Child::try_wait`, for a supervisor that keeps an eye on many processes
+    /// without awaiting any of them. Unlike `Child::try_wait`, the result can only be taken once,
+    /// since an error can't be copied: after that, or after awaiting the handle, this returns
+    /// `None` too.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::WasiProcessBuilder;
+    /// # let store = wasmer::Store::default();
+    /// let wat = r#"(module
+    ///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///     (memory (export "memory") 1)
+    ///     (func (export "_start") (call $proc_exit (i32.const 7))))"#;
+    /// let module = wasmer::Module::new(&store, wat)?;
+    /// let mut handles = Vec::new();
+    /// for _ in 0..3 {
+    ///     let store = wasmer::Store::new(store.engine().clone());
+    ///     handles.push(WasiProcessBuilder::new("exit", module.clone()).build(store)?.spawn());
+    /// }
+    /// let mut codes = Vec::new();
+    /// while !handles.is_empty() {
+    ///     handles.retain_mut(|handle| match handle.try_wait() {
+    ///         Some(res) => {
+    ///             codes.push(res.map(|status| status.code()));
+    ///             false
+    ///         }
+    ///         None => true,
+    ///     });
+    ///     tokio::task::yield_now().await;
+    /// }
+    /// assert_eq!(codes.into_iter().collect::<Result<Vec<_>, _>>()?, [7, 7, 7]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn try_wait(&mut self) -> Option<Result<ExitStatus, SpawnError>> {
+        if self.finished {
+            return None;
+        }
+        // this is only a check, so nothing needs to be woken once it would be ready
+        let mut cx = Context::from_waker(Waker::noop());
+        match Pin::new(self).poll(&mut cx) {
+            Poll::Ready(res) => Some(res),
+            Poll::Pending => None,
+        }
+    }
+
+    /// Wait for the process to finish, and get why it stopped, see [`TerminationReason`]. This
+    /// is awaiting the handle with the result turned into a reason, for callers that only want
+    /// to know how the module ended rather than handle the error.
+    ///
+    /// # Examples
+    /// ```
+    /// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use wasi_process2::{TerminationReason, WasiProcessBuilder};
+    /// # let store = wasmer::Store::default();
+    /// # let engine = store.engine().clone();
+    /// let modules = [
+    ///     // returns from `_start`
+    ///     r#"(func (export "_start"))"#,
+    ///     // exits with 3
+    ///     r#"(func (export "_start") (call $proc_exit (i32.const 3)))"#,
+    ///     r#"(func (export "_start") unreachable)"#,
+    ///     r#"(func (export "_start") (drop (i32.load (i32.const 100000))))"#,
+    ///     r#"(func $recurse (export "_start") (call $recurse))"#,
+    /// ];
+    /// let mut reasons = Vec::new();
+    /// for body in modules {
+    ///     let wat = format!(
+    ///         r#"(module
+    ///             (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+    ///             (memory (export "memory") 1)
+    ///             {})"#,
+    ///         body
+    ///     );
+    ///     # let store = wasmer::Store::new(engine.clone());
+    ///     let module = wasmer::Module::new(&store, wat)?;
+    ///     let wasi = WasiProcessBuilder::new("stop", module).build(store)?;
+    ///     reasons.push(wasi.spawn().termination().await);
+    /// }
+    /// assert_eq!(
+    ///     reasons,
+    ///     [
+    ///         TerminationReason::Exited(0),
+    ///         TerminationReason::Exited(3),
+    ///         TerminationReason::Unreachable,
+    ///         TerminationReason::MemoryOutOfBounds,
+    ///         TerminationReason::StackOverflow,
+    ///     ]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn termination(self) -> TerminationReason {
+        match self.await {
+            Ok(status) => TerminationReason::Exited(status.code()),
+            Err(e) => e.termination_reason(),
+        }
+    }
 }
 
 impl Future for SpawnHandle {
-    type Output = Result<(), SpawnError>;
+    type Output = Result<ExitStatus, SpawnError>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        Pin::new(&mut self.inner)
-            .poll(cx)
-            .map(|res| res.map_err(SpawnError::Join)?.map_err(SpawnError::Wasi))
+        if let Poll::Ready(res) = Pin::new(&mut self.inner).poll(cx) {
+            self.finished = true;
+            return Poll::Ready(
+                res.map_err(SpawnError::from_join)?
+                    .map_err(SpawnError::from_runtime),
+            );
+        }
+        // the module might never return, so don't wait for it once the process has been stopped
+        let stop = ready!(self.stopped.0.as_mut().poll(cx));
+        self.finished = true;
+        Poll::Ready(Err(SpawnError::from_runtime(stop.error())))
+    }
+}
+
+/// A cloneable handle for waiting on the result of a process, from
+/// [`WasiProcess::status_handle`].
+#[derive(Debug, Clone)]
+pub struct StatusHandle {
+    status: watch::Receiver<Option<Result<i32, RuntimeError>>>,
+}
+
+impl StatusHandle {
+    /// Wait for the process to finish, and get its exit code like [`Output::status`], or the
+    /// error it failed with. Returns `None` if the process was dropped without finishing, e.g.
+    /// because it was cancelled or its task panicked.
+    ///
+    /// This waits for the module itself; a [`SpawnError::IdleTimeout`] only shows up on the
+    /// [`SpawnHandle`], and this gets whatever the module did once its pipes were closed.
+    pub async fn wait(&mut self) -> Option<Result<i32, SpawnError>> {
+        let status = self.status.wait_for(Option::is_some).await.ok()?;
+        let res = status.clone()?;
+        Some(res.map_err(SpawnError::classify))
+    }
+}
+
+/// A cloneable handle for killing a process, from [`WasiProcess::kill_handle`] or
+/// [`SpawnHandle::kill_handle`]. Dropping it does nothing.
+#[derive(Debug)]
+pub struct KillHandle {
+    stop: Arc<watch::Sender<Option<Stop>>>,
+    /// Handles to the module's ends of the pipes, which don't close them when they're dropped.
+    pipes: Vec<LockPipe>,
+    shared: SharedPipes,
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Clone for KillHandle {
+    fn clone(&self) -> Self {
+        Self {
+            stop: self.stop.clone(),
+            pipes: self.pipes.iter().map(LockPipe::handle).collect(),
+            shared: self.shared.clone(),
+            paused: self.paused.clone(),
+        }
+    }
+}
+
+impl KillHandle {
+    /// Kill the process, see [`SpawnHandle::kill`].
+    pub fn kill(&self) {
+        stop(&self.stop, Stop::Killed);
+        close_all(&self.pipes, &self.shared, &self.paused);
+    }
+}
+
+/// The pipes a process's module was given with [`add_stdio_with_pipes`]. The module uses those
+/// directly rather than through the process, so the process only finds out about each one the
+/// first time the module uses it, and from then on closes it along with its own pipes.
+#[derive(Debug, Clone)]
+struct SharedPipes(Arc<Mutex<SharedPipesInner>>);
+
+#[derive(Debug)]
+struct SharedPipesInner {
+    /// The module's ends of them, which close them when they're dropped, and wait while the
+    /// process is [paused](SpawnHandle::pause).
+    pipes: Vec<LockPipe>,
+    /// Whether the process is paused, for the pipes that are found later.
+    paused: watch::Receiver<bool>,
+    /// Whether they've been [closed](SharedPipes::close), so one that's found after that is
+    /// closed right away.
+    closed: bool,
+}
+
+impl SharedPipes {
+    fn new(paused: watch::Receiver<bool>) -> Self {
+        Self(Arc::new(Mutex::new(SharedPipesInner {
+            pipes: Vec::new(),
+            paused,
+            closed: false,
+        })))
+    }
+
+    /// Let the process know the module is using `pipe`, if it doesn't already, and get the
+    /// process's handle to it.
+    fn register(&self, pipe: &LockPipe) -> LockPipe {
+        let mut inner = self.0.lock();
+        if let Some(known) = inner.pipes.iter().find(|known| known.same_pipe(pipe)) {
+            return known.handle();
+        }
+        if inner.closed {
+            pipe.close();
+        }
+        let mut end = pipe.clone_end();
+        end.set_paused(inner.paused.clone());
+        let handle = end.handle();
+        inner.pipes.push(end);
+        handle
+    }
+
+    /// Close all of the pipes, and any that are found from now on.
+    fn close(&self) {
+        let mut inner = self.0.lock();
+        inner.closed = true;
+        for pipe in &inner.pipes {
+            pipe.close();
+        }
+    }
+
+    /// Close the module's ends of the pipes once it's returned.
+    fn close_ends(&self) {
+        self.0.lock().pipes.clear();
+    }
+
+    /// Handles to the pipes found so far.
+    fn handles(&self) -> Vec<LockPipe> {
+        self.0.lock().pipes.iter().map(LockPipe::handle).collect()
+    }
+}
+
+/// What stopped a process from the outside, before its module returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Killed,
+    TimedOut,
+}
+
+impl Stop {
+    /// The error the process fails with for it.
+    fn error(self) -> RuntimeError {
+        match self {
+            Self::Killed => RuntimeError::user(Box::new(Killed)),
+            Self::TimedOut => RuntimeError::user(Box::new(TimedOut)),
+        }
+    }
+}
+
+/// Record that the process was stopped with `reason`, unless it already was.
+fn stop(stop: &watch::Sender<Option<Stop>>, reason: Stop) {
+    stop.send_if_modified(|stop| {
+        let first = stop.is_none();
+        if first {
+            *stop = Some(reason);
+        }
+        first
+    });
+}
+
+/// Resolves once the process has been stopped from the outside, so that a [`SpawnHandle`]
+/// doesn't have to wait for a module that might never return.
+struct Stopped(Pin<Box<dyn Future<Output = Stop> + Send + Sync>>);
+
+impl Stopped {
+    fn new(mut stop: watch::Receiver<Option<Stop>>) -> Self {
+        Self(Box::pin(async move {
+            let stopped = stop.wait_for(Option::is_some).await.map(|stop| *stop);
+            match stopped {
+                Ok(stop) => stop.expect("waited for it to be set"),
+                // there's nothing left that could stop the process
+                Err(_) => std::future::pending().await,
+            }
+        }))
+    }
+}
+
+impl fmt::Debug for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Stopped")
+    }
+}
+
+/// The handle's side of [`SpawnHandle::pause`], which resumes the process when it's dropped, so
+/// that a paused process doesn't wait forever once there's nothing left to resume it.
+#[derive(Debug)]
+struct Pause(Arc<watch::Sender<bool>>);
+
+impl Drop for Pause {
+    fn drop(&mut self) {
+        self.0.send_replace(false);
+    }
+}
+
+/// Close all of `pipes` and `shared`, and resume the process if it's paused, so that a module
+/// waiting to be resumed gets to see that they're closed.
+fn close_all<'a>(
+    pipes: impl IntoIterator<Item = &'a LockPipe>,
+    shared: &SharedPipes,
+    paused: &watch::Sender<bool>,
+) {
+    for pipe in pipes {
+        pipe.close();
+    }
+    shared.close();
+    paused.send_replace(false);
+}
+
+/// Kills a process when it's dropped, if it hasn't finished, see [`WasiProcess::kill_on_drop`].
+/// This is its own type rather than `SpawnHandle`'s `Drop`, so the task can still be moved out
+/// of a handle to be awaited.
+#[derive(Debug)]
+struct KillOnDrop {
+    kill: KillHandle,
+    task: task::AbortHandle,
+}
+
+impl KillOnDrop {
+    fn new<T>(kill: KillHandle, task: &task::JoinHandle<T>) -> Self {
+        Self {
+            kill,
+            task: task.abort_handle(),
+        }
+    }
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if !self.task.is_finished() {
+            self.kill.kill();
+            self.task.abort();
+        }
+    }
+}
+
+/// Stop the process with [`Stop::TimedOut`] and close all of `pipes` and `shared` once `timeout`
+/// has passed.
+async fn watch_timeout(
+    pipes: Vec<LockPipe>,
+    shared: SharedPipes,
+    paused: Arc<watch::Sender<bool>>,
+    timeout: Duration,
+    stop: Arc<watch::Sender<Option<Stop>>>,
+) {
+    tokio::time::sleep(timeout).await;
+    self::stop(&stop, Stop::TimedOut);
+    close_all(&pipes, &shared, &paused);
+}
+
+/// Close all of `pipes` and `shared` and set `timed_out` once none of them have been read from
+/// or written to for `idle`.
+async fn watch_idle(
+    pipes: Vec<LockPipe>,
+    shared: SharedPipes,
+    paused: Arc<watch::Sender<bool>>,
+    idle: Duration,
+    timed_out: Arc<AtomicBool>,
+) {
+    let progress = || -> u64 {
+        pipes
+            .iter()
+            .chain(&shared.handles())
+            .map(|pipe| {
+                let metrics = pipe.metrics();
+                metrics.bytes_written + metrics.bytes_read
+            })
+            .sum()
+    };
+    let mut last = progress();
+    let mut last_at = Instant::now();
+    let mut ticks = tokio::time::interval((idle / 8).max(Duration::from_millis(1)));
+    loop {
+        ticks.tick().await;
+        let now = progress();
+        if now != last {
+            last = now;
+            last_at = Instant::now();
+        } else if last_at.elapsed() >= idle {
+            timed_out.store(true, Ordering::Release);
+            close_all(&pipes, &shared, &paused);
+            return;
+        }
+    }
+}
+
+/// The error a process spawned with [`WasiProcess::spawn_idle_timeout`] fails with when it's
+/// given up on, turned into [`SpawnError::IdleTimeout`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct IdleTimeout;
+
+impl fmt::Display for IdleTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the process made no stdio progress before the idle timeout")
     }
 }
 
+impl std::error::Error for IdleTimeout {}
+
+/// The error a process fails with when it runs out of [time](WasiProcess::timeout), turned
+/// into [`SpawnError::TimedOut`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the process ran past its timeout")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// The error a process fails with once it's been [killed](SpawnHandle::kill), turned into
+/// [`SpawnError::Killed`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct Killed;
+
+impl fmt::Display for Killed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the process was killed")
+    }
+}
+
+impl std::error::Error for Killed {}
+
+/// The error a metered process fails with when it runs out of points, turned into
+/// [`SpawnError::OutOfFuel`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct OutOfFuel;
+
+impl fmt::Display for OutOfFuel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the module ran out of fuel")
+    }
+}
+
+impl std::error::Error for OutOfFuel {}
+
+/// The error a process fails with when it writes more than its
+/// [output limit](WasiProcess::limit_output_bytes), turned into
+/// [`SpawnError::OutputLimitExceeded`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct OutputLimitExceeded;
+
+impl fmt::Display for OutputLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the process wrote more output than its limit")
+    }
+}
+
+impl std::error::Error for OutputLimitExceeded {}
+
+/// The error a process fails with when it fails after running into its
+/// [memory limit](WasiProcessBuilder::max_memory_pages), turned into
+/// [`SpawnError::OutOfMemory`] by [`SpawnHandle`].
+#[derive(Debug)]
+struct OutOfMemory;
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("the module ran out of memory")
+    }
+}
+
+impl std::error::Error for OutOfMemory {}
+
 /// An error returned from a spawned process. Either an error from tokio's `task::spawn`, such as a
-/// panic or cancellation, or a wasm/wasi error, like an `_exit()` call or an unreachable.
+/// panic or cancellation, or a wasm/wasi error, like an unreachable. A module calling
+/// `proc_exit` isn't an error, but an [`ExitStatus`].
+///
+/// The underlying error is available through `Error::source`. More variants can be added as
+/// processes get new limits, so a `match` on it needs a wildcard arm.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() {
+/// use std::error::Error;
+/// use wasi_process2::SpawnError;
+/// let err = SpawnError::Wasi(wasmer::RuntimeError::new("unreachable"));
+/// assert!(err.source().unwrap().is::<wasmer::RuntimeError>());
+///
+/// let task = tokio::spawn(std::future::pending::<()>());
+/// task.abort();
+/// let err = SpawnError::Join(task.await.unwrap_err());
+/// assert!(err.source().unwrap().is::<tokio::task::JoinError>());
+/// # }
+/// ```
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum SpawnError {
     /// An error received from wasmer
     Wasi(RuntimeError),
     /// An error from `tokio::task::spawn`
     Join(tokio::task::JoinError),
+    /// A process created with [`WasiProcess::new_metered`] used up all of its points
+    OutOfFuel,
+    /// A process spawned with [`WasiProcess::spawn_idle_timeout`] didn't use its stdio for too
+    /// long
+    IdleTimeout,
+    /// A process wrote more to stdout and stderr than allowed by
+    /// [`WasiProcess::limit_output_bytes`]
+    OutputLimitExceeded,
+    /// A process failed after trying to grow its memory past
+    /// [`WasiProcessBuilder::max_memory_pages`]
+    OutOfMemory,
+    /// A process was stopped with [`SpawnHandle::kill`]
+    Killed,
+    /// A process ran for longer than its [`WasiProcess::timeout`]
+    TimedOut,
+}
+
+impl SpawnError {
+    fn from_runtime(e: RuntimeError) -> Self {
+        Self::classify(e).traced()
+    }
+
+    /// Turn the marker errors back into their variants.
+    fn classify(e: RuntimeError) -> Self {
+        if e.is::<OutOfFuel>() {
+            Self::OutOfFuel
+        } else if e.is::<IdleTimeout>() {
+            Self::IdleTimeout
+        } else if e.is::<OutputLimitExceeded>() {
+            Self::OutputLimitExceeded
+        } else if e.is::<OutOfMemory>() {
+            Self::OutOfMemory
+        } else if e.is::<Killed>() {
+            Self::Killed
+        } else if e.is::<TimedOut>() {
+            Self::TimedOut
+        } else {
+            Self::Wasi(e)
+        }
+    }
+
+    fn from_join(e: task::JoinError) -> Self {
+        Self::Join(e).traced()
+    }
+
+    /// Get why the process stopped, see [`TerminationReason`]. A `Wasi` error made from a
+    /// `proc_exit` is [`TerminationReason::Exited`] here.
+    pub fn termination_reason(&self) -> TerminationReason {
+        match self {
+            Self::Wasi(e) => TerminationReason::from_runtime(e),
+            Self::Join(_) => TerminationReason::Aborted,
+            Self::OutOfFuel => TerminationReason::OutOfFuel,
+            Self::IdleTimeout => TerminationReason::IdleTimeout,
+            Self::OutputLimitExceeded => TerminationReason::OutputLimitExceeded,
+            Self::OutOfMemory => TerminationReason::OutOfMemory,
+            Self::Killed => TerminationReason::Killed,
+            Self::TimedOut => TerminationReason::TimedOut,
+        }
+    }
+
+    /// Display the error along with the name of the process it came from, e.g. from
+    /// [`SpawnHandle::name`], so errors from different processes can be told apart. Without a
+    /// name, this displays just like the error itself.
+    pub fn with_name<'a>(&'a self, name: Option<&'a str>) -> impl fmt::Display + 'a {
+        struct Named<'a>(&'a SpawnError, Option<&'a str>);
+        impl fmt::Display for Named<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self.1 {
+                    Some(name) => write!(f, "{}: {}", name, self.0),
+                    None => self.0.fmt(f),
+                }
+            }
+        }
+        Named(self, name)
+    }
+
+    fn traced(self) -> Self {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(error = %self, "process failed");
+        self
+    }
 }
 
 impl fmt::Display for SpawnError {
@@ -237,8 +5298,82 @@ impl fmt::Display for SpawnError {
         match self {
             Self::Wasi(w) => write!(f, "runtime wasi/wasm error: {}", w),
             Self::Join(j) => write!(f, "error while joining the tokio task: {}", j),
+            Self::OutOfFuel => write!(f, "{}", OutOfFuel),
+            Self::IdleTimeout => write!(f, "{}", IdleTimeout),
+            Self::OutputLimitExceeded => write!(f, "{}", OutputLimitExceeded),
+            Self::OutOfMemory => write!(f, "{}", OutOfMemory),
+            Self::Killed => write!(f, "{}", Killed),
+            Self::TimedOut => write!(f, "{}", TimedOut),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Wasi(w) => Some(w),
+            Self::Join(j) => Some(j),
+            Self::OutOfFuel
+            | Self::IdleTimeout
+            | Self::OutputLimitExceeded
+            | Self::OutOfMemory
+            | Self::Killed
+            | Self::TimedOut => None,
         }
     }
 }
 
-impl std::error::Error for SpawnError {}
+/// Why a process stopped: it exited, a trap it hit, or one of the limits it ran into. Get it
+/// with [`SpawnHandle::termination`], or from a failed process's error with
+/// [`SpawnError::termination_reason`]. Like [`SpawnError`], it can get more variants.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TerminationReason {
+    /// `_start` returned, which is exit code 0, or the module called `proc_exit` with this code
+    Exited(i32),
+    /// The module reached an `unreachable` instruction, e.g. because it panicked
+    Unreachable,
+    /// The module accessed its memory out of bounds
+    MemoryOutOfBounds,
+    /// The module ran out of stack, e.g. from infinite recursion
+    StackOverflow,
+    /// The module hit some other trap, like a division by zero or a bad indirect call
+    OtherTrap,
+    /// See [`SpawnError::OutOfFuel`]
+    OutOfFuel,
+    /// See [`SpawnError::IdleTimeout`]
+    IdleTimeout,
+    /// See [`SpawnError::OutputLimitExceeded`]
+    OutputLimitExceeded,
+    /// See [`SpawnError::OutOfMemory`]
+    OutOfMemory,
+    /// See [`SpawnError::Killed`]
+    Killed,
+    /// See [`SpawnError::TimedOut`]
+    TimedOut,
+    /// The process's task panicked or was cancelled, see [`SpawnError::Join`]
+    Aborted,
+    /// Some other error, e.g. from a host function
+    Error,
+}
+
+impl TerminationReason {
+    /// Look at what's behind `e`: the error's source is the `WasiError` for `proc_exit`, or the
+    /// trap code for a trap.
+    fn from_runtime(e: &RuntimeError) -> Self {
+        let source = match std::error::Error::source(e) {
+            Some(source) => source,
+            None => return Self::Error,
+        };
+        if let Some(WasiError::Exit(code)) = source.downcast_ref() {
+            return Self::Exited(*code as i32);
+        }
+        match source.downcast_ref() {
+            Some(TrapCode::UnreachableCodeReached) => Self::Unreachable,
+            Some(TrapCode::HeapAccessOutOfBounds) => Self::MemoryOutOfBounds,
+            Some(TrapCode::StackOverflow) => Self::StackOverflow,
+            Some(_) => Self::OtherTrap,
+            None => Self::Error,
+        }
+    }
+}
@@ -0,0 +1,103 @@
+//! Reading a process's stdout line by line, see [`WasiStdout::lines`].
+
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io;
+
+use super::WasiStdout;
+
+/// The lines of a process's stdout as bytes, from [`WasiStdout::byte_lines`].
+pub struct StdoutByteLines {
+    stdout: WasiStdout,
+    /// The bytes read from stdout that aren't part of a returned line yet.
+    buf: Vec<u8>,
+    /// How much of `buf` is known not to have a `\n` in it.
+    scanned: usize,
+    /// Whether stdout has hit EOF.
+    eof: bool,
+}
+
+impl StdoutByteLines {
+    pub(crate) fn new(stdout: WasiStdout) -> Self {
+        Self {
+            stdout,
+            buf: Vec::new(),
+            scanned: 0,
+            eof: false,
+        }
+    }
+
+    /// Get the next line, without the `\n` or `\r\n` at the end of it, or `None` at EOF.
+    pub async fn next_line(&mut self) -> Option<Vec<u8>> {
+        poll_fn(|cx| self.poll_line(cx)).await
+    }
+
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<Option<Vec<u8>>> {
+        loop {
+            if let Some(i) = self.buf[self.scanned..].iter().position(|&b| b == b'\n') {
+                let mut line: Vec<u8> = self.buf.drain(..=self.scanned + i).collect();
+                self.scanned = 0;
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Poll::Ready(Some(line));
+            }
+            self.scanned = self.buf.len();
+            if self.eof {
+                // whatever's left is the last line, even without a `\n`
+                self.scanned = 0;
+                let rest = std::mem::take(&mut self.buf);
+                return Poll::Ready(if rest.is_empty() { None } else { Some(rest) });
+            }
+            let chunk = ready!(self.stdout.inner.poll_read_bytes(cx, usize::MAX));
+            if chunk.is_empty() {
+                self.eof = true;
+            } else {
+                self.buf.extend_from_slice(&chunk);
+            }
+        }
+    }
+}
+
+impl Stream for StdoutByteLines {
+    type Item = Vec<u8>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_line(cx)
+    }
+}
+
+/// The lines of a process's stdout as strings, from [`WasiStdout::lines`].
+pub struct StdoutLines {
+    inner: StdoutByteLines,
+}
+
+impl StdoutLines {
+    pub(crate) fn new(stdout: WasiStdout) -> Self {
+        Self {
+            inner: StdoutByteLines::new(stdout),
+        }
+    }
+
+    /// Get the next line, without the `\n` or `\r\n` at the end of it, or `None` at EOF. A line
+    /// that isn't valid UTF-8 is an `InvalidData` error, and reading can go on after it.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        poll_fn(|cx| self.poll_line(cx)).await.transpose()
+    }
+
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<String>>> {
+        let line = ready!(self.inner.poll_line(cx));
+        Poll::Ready(line.map(|line| {
+            String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }))
+    }
+}
+
+impl Stream for StdoutLines {
+    type Item = io::Result<String>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_line(cx)
+    }
+}
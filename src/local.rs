@@ -0,0 +1,162 @@
+//! Processes for stores that aren't `Send`, see [`LocalWasiProcess`].
+
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::task;
+use wasmer::{AsStoreMut, RuntimeError};
+
+use super::{
+    check_start, get_start, ExitStatus, KillOnDrop, MaxBufSize, NewError, Pause, SpawnHandle,
+    Stopped, StoreSlot, WasiProcess,
+};
+use super::{stdio, LockPipe, ModuleStdio, STDIO};
+
+type LocalProcessFuture = Pin<Box<dyn Future<Output = Result<(), RuntimeError>>>>;
+type LocalStartFn = Box<dyn FnOnce(ModuleStdio) -> LocalProcessFuture>;
+
+/// A wasi process whose store doesn't have to be `Send` or `Sync`, so it can't be moved to
+/// another thread and has to be run on a `LocalSet` with [`spawn_local`](Self::spawn_local), or
+/// awaited directly.
+///
+/// It derefs to a [`WasiProcess`] for the stdio streams and extra pipes, but the process itself
+/// is run through this type. The module runs right on the thread that polls it, which blocks
+/// that thread, and the rest of its `LocalSet`, until `_start` returns. So if the module might
+/// wait on a pipe, the host end of it has to be driven from another thread, e.g. with
+/// `tokio::spawn` on a multi-threaded runtime. Since the store never leaves the process,
+/// [`SpawnHandle::inspect`] always returns `None` for it.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main(flavor = "current_thread")] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use tokio::io::AsyncReadExt;
+/// # use wasmer_wasi::WasiState;
+/// use wasi_process2::{LocalWasiProcess, MaxBufSize};
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_bytes!("../helloworld.wasm"))?;
+/// # let mut state = WasiState::new("progg");
+/// # wasi_process2::add_stdio(&mut state);
+/// # let mut env = state.finalize(&mut store)?;
+/// # let imports = env.import_object(&mut store, &module)?;
+/// # let instance = wasmer::Instance::new(&mut store, &module, &imports)?;
+/// # env.initialize(&mut store, &instance)?;
+/// let mut wasi = LocalWasiProcess::new(store, &instance, MaxBufSize::default())?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// let local = tokio::task::LocalSet::new();
+/// local
+///     .run_until(async move {
+///         wasi.spawn_local();
+///         let mut out = String::new();
+///         stdout.read_to_string(&mut out).await?;
+///         assert_eq!(out, "Hello, World!\n");
+///         Ok::<_, Box<dyn std::error::Error>>(())
+///     })
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[must_use = "LocalWasiProcess does nothing without being polled or spawned. Try calling \
+              `.spawn_local()`"]
+pub struct LocalWasiProcess {
+    process: WasiProcess,
+    start: Option<LocalStartFn>,
+    handle: Option<LocalProcessFuture>,
+}
+
+impl LocalWasiProcess {
+    /// Create a LocalWasiProcess from a wasm instance, like [`WasiProcess::new`].
+    pub fn new(
+        store: impl AsStoreMut + 'static,
+        instance: &wasmer::Instance,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        let start = get_start(instance, "_start")?;
+        Self::with_function(store, start, buf_size)
+    }
+
+    /// Create a LocalWasiProcess from a `_start` function, like [`WasiProcess::with_function`].
+    pub fn with_function(
+        mut store: impl AsStoreMut + 'static,
+        start_function: wasmer::Function,
+        buf_size: MaxBufSize,
+    ) -> Result<Self, NewError> {
+        check_start(&store, &start_function)?;
+        buf_size.check()?;
+        let start: LocalStartFn = Box::new(move |stdio| {
+            Box::pin(STDIO.scope(stdio, async move {
+                let res = start_function
+                    .call(&mut store.as_store_mut(), &[])
+                    .map(drop);
+                stdio::flush_staged();
+                res
+            }))
+        });
+        Ok(Self {
+            process: WasiProcess::with_stdio(buf_size, StoreSlot::default()),
+            start: Some(start),
+            handle: None,
+        })
+    }
+
+    /// Spawn the process on the current `LocalSet` with `task::spawn_local`. Like
+    /// [`WasiProcess::spawn`], it's okay to let the handle drop.
+    pub fn spawn_local(self) -> SpawnHandle {
+        let store = self.process.store.clone();
+        let returns = self.process.returns.clone();
+        let name = self.process.program_name.clone();
+        let kill = self.process.kill_handle();
+        let kill_on_drop = self.process.kill_on_drop;
+        let stdin = self.process.pipes.get(&0).map(LockPipe::handle);
+        let pause = Pause(self.process.paused.clone());
+        let stopped = Stopped::new(self.process.stop.subscribe());
+        let inner = task::spawn_local(self);
+        SpawnHandle {
+            _kill_on_drop: kill_on_drop.then(|| KillOnDrop::new(kill.clone(), &inner)),
+            inner,
+            instance: None,
+            store,
+            returns,
+            name,
+            kill,
+            stdin,
+            pause,
+            stopped,
+            yields: false,
+            finished: false,
+        }
+    }
+}
+
+impl Deref for LocalWasiProcess {
+    type Target = WasiProcess;
+    fn deref(&self) -> &WasiProcess {
+        &self.process
+    }
+}
+
+impl DerefMut for LocalWasiProcess {
+    fn deref_mut(&mut self) -> &mut WasiProcess {
+        &mut self.process
+    }
+}
+
+impl Future for LocalWasiProcess {
+    type Output = Result<ExitStatus, RuntimeError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let handle = match &mut this.handle {
+            Some(handle) => handle,
+            None => {
+                let start = this.start.take().expect("the start fn is only taken once");
+                this.process.start_timeout();
+                let process = start(this.process.module_stdio());
+                #[cfg(feature = "tracing")]
+                let process = Box::pin(super::traced(process, None));
+                this.handle.insert(process)
+            }
+        };
+        let res = ready!(handle.as_mut().poll(cx));
+        Poll::Ready(this.process.finish(res))
+    }
+}
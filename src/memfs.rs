@@ -0,0 +1,101 @@
+//! A filesystem kept in memory, for running modules without touching the host's disk, see
+//! [`MemFs`].
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use wasmer_vfs::{mem_fs, FileSystem, FsError};
+
+/// A filesystem that lives in memory, for modules that read and write files in a sandbox. Give
+/// it to a process with [`WasiProcessBuilder::mem_fs`](super::WasiProcessBuilder::mem_fs),
+/// which preopens it at `/`.
+///
+/// Clones share the same files, so keep one around to put input files in before the process
+/// runs and to read what it wrote afterwards. Paths are absolute; relative ones are taken to be
+/// relative to `/`.
+///
+/// # Examples
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use wasi_process2::MemFs;
+/// let fs = MemFs::new();
+/// fs.write("/data/in.txt", b"hello")?;
+/// assert_eq!(fs.clone().read("data/in.txt")?, b"hello");
+/// assert_eq!(fs.read("/nope").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemFs {
+    inner: mem_fs::FileSystem,
+}
+
+impl MemFs {
+    /// Create an empty filesystem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create the file at `path`, or replace what's in it, with `data`. Its parent directories
+    /// are created as needed.
+    pub fn write(&self, path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+        let path = absolute(path.as_ref());
+        if let Some(parent) = path.parent() {
+            let mut dirs: Vec<_> = parent.ancestors().collect();
+            // from the top down, skipping the root, which always exists
+            dirs.reverse();
+            for dir in dirs.into_iter().skip(1) {
+                match self.inner.create_dir(dir) {
+                    Ok(()) | Err(FsError::AlreadyExists) => {}
+                    Err(e) => return Err(to_io(e)),
+                }
+            }
+        }
+        let mut file = self
+            .inner
+            .new_open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(to_io)?;
+        file.write_all(data)
+    }
+
+    /// Read the whole file at `path`.
+    pub fn read(&self, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+        let path = absolute(path.as_ref());
+        // the memory filesystem reports opening a file that isn't there as a permission error
+        if self.inner.metadata(&path).is_err() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        let mut file = self
+            .inner
+            .new_open_options()
+            .read(true)
+            .open(&path)
+            .map_err(to_io)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// The filesystem to give to wasi, sharing the files with this one.
+    pub(crate) fn backing(&self) -> Box<dyn FileSystem> {
+        Box::new(self.inner.clone())
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    Path::new("/").join(path)
+}
+
+fn to_io(e: FsError) -> io::Error {
+    let kind = match e {
+        FsError::EntityNotFound => io::ErrorKind::NotFound,
+        FsError::AlreadyExists => io::ErrorKind::AlreadyExists,
+        FsError::PermissionDenied => io::ErrorKind::PermissionDenied,
+        FsError::InvalidInput => io::ErrorKind::InvalidInput,
+        _ => io::ErrorKind::Other,
+    };
+    io::Error::new(kind, e)
+}
@@ -0,0 +1,157 @@
+//! Capping how far a module's linear memory can grow, see
+//! [`WasiProcessBuilder::max_memory_pages`](super::WasiProcessBuilder::max_memory_pages).
+
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wasmer::vm::{
+    MemoryError, MemoryStyle, TableStyle, VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition,
+};
+use wasmer::{BaseTunables, MemoryType, Pages, TableType, Tunables};
+use wasmer_vm::{LinearMemory, Trap};
+
+/// The most pages a process's memories can have, and whether the module has run into that.
+#[derive(Debug)]
+pub(crate) struct MemoryLimit {
+    max: Pages,
+    exceeded: AtomicBool,
+}
+
+impl MemoryLimit {
+    pub fn new(max_pages: u32) -> Self {
+        Self {
+            max: Pages(max_pages),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a `memory.grow` has been refused because it would have gone over the limit.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Acquire)
+    }
+
+    /// Fail if a memory of type `ty` starts out bigger than the limit.
+    fn check(&self, ty: &MemoryType) -> Result<(), MemoryError> {
+        if ty.minimum > self.max {
+            return Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: self.max,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Tunables that create every memory with a [`MemoryLimit`], and otherwise do whatever `base`
+/// does.
+pub(crate) struct LimitingTunables {
+    base: BaseTunables,
+    limit: Arc<MemoryLimit>,
+}
+
+impl LimitingTunables {
+    pub fn new(base: BaseTunables, limit: Arc<MemoryLimit>) -> Self {
+        Self { base, limit }
+    }
+
+    fn limited(&self, memory: VMMemory) -> VMMemory {
+        VMMemory(Box::new(LimitedMemory {
+            inner: memory.0,
+            limit: self.limit.clone(),
+        }))
+    }
+}
+
+impl Tunables for LimitingTunables {
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.limit.check(ty)?;
+        let memory = self.base.create_host_memory(ty, style)?;
+        Ok(self.limited(memory))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        self.limit.check(ty)?;
+        let memory = self
+            .base
+            .create_vm_memory(ty, style, vm_definition_location)?;
+        Ok(self.limited(memory))
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(ty, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base.create_vm_table(ty, style, vm_definition_location)
+    }
+}
+
+/// A memory that refuses to grow past its [`MemoryLimit`], and notes it down when it does.
+#[derive(Debug)]
+struct LimitedMemory {
+    inner: Box<dyn LinearMemory + 'static>,
+    limit: Arc<MemoryLimit>,
+}
+
+impl LinearMemory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.inner.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.inner.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.inner.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let current = self.inner.size();
+        if current.0.saturating_add(delta.0) > self.limit.max.0 {
+            self.limit.exceeded.store(true, Ordering::Release);
+            return Err(MemoryError::CouldNotGrow {
+                current,
+                attempted_delta: delta,
+            });
+        }
+        self.inner.grow(delta)
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.inner.vmmemory()
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn LinearMemory + 'static>> {
+        Some(Box::new(Self {
+            inner: self.inner.try_clone()?,
+            limit: self.limit.clone(),
+        }))
+    }
+
+    unsafe fn initialize_with_data(&self, start: usize, data: &[u8]) -> Result<(), Trap> {
+        self.inner.initialize_with_data(start, data)
+    }
+}
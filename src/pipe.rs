@@ -1,16 +1,45 @@
 //! A unidirectional pipe implementation modified from private module tokio::io::util::mem; it's
 //! the backing data structure behind DuplexStream
 
-use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
 use tokio::io::{self, AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use std::{
+    io::IoSlice,
     pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     sync::Arc,
     task::{self, Poll, Waker},
 };
 
+/// The lock around a pipe, and everything else in the crate that needs one: `parking_lot`'s by
+/// default, or the standard library's without the `parking_lot` feature.
+#[cfg(feature = "parking_lot")]
+pub(crate) type Mutex<T> = parking_lot::Mutex<T>;
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) type Mutex<T> = StdMutex<T>;
+
+/// A `std::sync::Mutex` that locks like `parking_lot`'s, ignoring poisoning. Nothing that's
+/// locked is left broken by a panic partway through, at worst a write is cut short.
+#[cfg(not(feature = "parking_lot"))]
+#[derive(Debug, Default)]
+pub(crate) struct StdMutex<T>(std::sync::Mutex<T>);
+
+#[cfg(not(feature = "parking_lot"))]
+impl<T> StdMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self(std::sync::Mutex::new(value))
+    }
+
+    pub fn lock(&self) -> std::sync::MutexGuard<'_, T> {
+        self.0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
 /// A unidirectional IO over a piece of memory.
 ///
 /// Data can be written to the pipe, and reading will return that data.
@@ -22,8 +51,11 @@ pub struct Pipe {
     /// functionality already. Additionally, it can try to copy data in the
     /// same buffer if there read index has advanced far enough.
     buffer: BytesMut,
-    /// Determines if the write side has been closed.
-    is_closed: bool,
+    /// Determines if the write side has been closed. Reads return EOF once the buffer is empty.
+    write_closed: bool,
+    /// Determines if the read side has been closed. Nothing will read the buffer anymore, so
+    /// writes fail with `BrokenPipe`.
+    read_closed: bool,
     /// The maximum amount of bytes that can be written before returning
     /// `Poll::Pending`.
     max_buf_size: usize,
@@ -33,29 +65,616 @@ pub struct Pipe {
     /// If the `write` side has filled the `max_buf_size` and returned
     /// `Poll::Pending`, this is the waker for that parked task.
     write_waker: Option<Waker>,
+    /// The total amount of bytes written to the pipe.
+    bytes_written: u64,
+    /// The total amount of bytes read from the pipe.
+    bytes_read: u64,
+    /// The most bytes that have been in `buffer` at once.
+    max_len_seen: usize,
+    /// The number of writes that have put data into `buffer`.
+    writes: u64,
+    /// The number of writes that needed a bigger allocation for `buffer`.
+    allocations: u64,
+    /// The budget that writes to this pipe are taken from, if any.
+    output_limit: Option<Arc<OutputLimit>>,
+    /// The end that made the last operations, and how many it made in a row.
+    streak: (End, u32),
+    /// Whether a full buffer drops its oldest bytes to make room for a write, rather than making
+    /// the writer wait, see [`WasiProcess::stderr_tail_bytes`](crate::WasiProcess::stderr_tail_bytes).
+    ring: bool,
 }
 
-#[derive(Debug, Clone)]
+/// How many operations one end can make in a row before it has to give the other end a turn.
+const FAIR_STREAK: u32 = 64;
+
+/// A snapshot of the counters of a stdio pipe.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// # use wasi_process2::WasiProcessBuilder;
+/// # let mut store = wasmer::Store::default();
+/// # let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let mut wasi = WasiProcessBuilder::new("echo", module).build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// stdin.write_all(&[b'a'; 100]).await?;
+/// let metrics = stdin.metrics();
+/// assert_eq!((metrics.bytes_written, metrics.bytes_read), (100, 0));
+/// assert_eq!((metrics.current_len, metrics.max_len_seen), (100, 100));
+///
+/// wasi.spawn();
+/// stdin.shutdown().await?;
+/// let mut out = Vec::new();
+/// stdout.read_to_end(&mut out).await?;
+/// assert_eq!(stdin.metrics().bytes_read, 100);
+/// let metrics = stdout.metrics();
+/// assert_eq!((metrics.bytes_written, metrics.bytes_read), (104, 104));
+/// assert_eq!(metrics.current_len, 0);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PipeMetrics {
+    /// The total amount of bytes written to the pipe
+    pub bytes_written: u64,
+    /// The total amount of bytes read from the pipe
+    pub bytes_read: u64,
+    /// The amount of bytes currently buffered in the pipe
+    pub current_len: usize,
+    /// The most bytes that have been buffered in the pipe at once
+    pub max_len_seen: usize,
+    /// The number of writes that have put data into the pipe, each of which took its lock
+    pub writes: u64,
+    /// The number of writes that couldn't fit in the memory the pipe already had, and had to
+    /// allocate a bigger buffer, see
+    /// [`WasiProcess::preallocate_buffers`](crate::WasiProcess::preallocate_buffers)
+    pub allocations: u64,
+}
+
+/// Whether counting `len` more bytes on top of `count` passes a multiple of a MiB, which is
+/// when a tracing event is emitted for a pipe.
+#[cfg(feature = "tracing")]
+fn crossed_trace_threshold(count: u64, len: usize) -> bool {
+    const THRESHOLD: u64 = 1 << 20;
+    count / THRESHOLD != (count + len as u64) / THRESHOLD
+}
+
+/// The state of a stdio pipe that can be saved and restored later, e.g. to checkpoint input that
+/// the module hasn't read yet, or output nobody has read yet. It doesn't include the counters in
+/// [`PipeMetrics`].
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use wasi_process2::{NewError, PipeSnapshot, WasiProcessBuilder};
+/// # let mut store = wasmer::Store::default();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// # let engine = store.engine().clone();
+/// let mut wasi = WasiProcessBuilder::new("echo", module.clone()).build(store)?;
+/// let mut stdin = wasi.stdin.take().unwrap();
+/// stdin.write_all(b"saved input").await?;
+/// stdin.shutdown().await?;
+/// let snapshot = stdin.snapshot();
+/// assert_eq!(snapshot.data, b"saved input");
+/// assert!(snapshot.is_closed);
+///
+/// # let store = wasmer::Store::new(engine.clone());
+/// let mut restored = WasiProcessBuilder::new("echo", module).build(store)?;
+/// let restored_stdin = restored.stdin.take().unwrap();
+/// // one with no room in it, e.g. a corrupted one that's been deserialized, is rejected
+/// let empty = PipeSnapshot { max_buf_size: 0, ..snapshot.clone() };
+/// let err = restored_stdin.restore(&empty).unwrap_err();
+/// assert!(matches!(err, NewError::ZeroBufSize("stdin")));
+/// restored_stdin.restore(&snapshot)?;
+/// let mut stdout = restored.stdout.take().unwrap();
+/// restored.spawn();
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "saved inputEND\n");
+///
+/// // the module's done writing, so output restored after that still ends in EOF
+/// let unread = PipeSnapshot { data: b"again".to_vec(), is_closed: false, max_buf_size: 64 };
+/// stdout.restore(&unread)?;
+/// out.clear();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "again");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipeSnapshot {
+    /// The bytes buffered in the pipe
+    pub data: Vec<u8>,
+    /// Whether the writing end was closed
+    pub is_closed: bool,
+    /// The maximum size of the buffer
+    pub max_buf_size: usize,
+}
+
+/// A budget of bytes shared by the pipes a module writes its output to, see
+/// [`WasiProcess::limit_output_bytes`](crate::WasiProcess::limit_output_bytes).
+#[derive(Debug)]
+pub struct OutputLimit {
+    remaining: AtomicU64,
+    exceeded: AtomicBool,
+}
+
+impl OutputLimit {
+    pub fn new(max: u64) -> Self {
+        Self {
+            remaining: AtomicU64::new(max),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether a write has been refused because the budget was used up.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Acquire)
+    }
+
+    /// Take up to `len` bytes from the budget, returning how many were taken.
+    fn take(&self, len: usize) -> usize {
+        let mut taken = 0;
+        let _ = self
+            .remaining
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |remaining| {
+                taken = remaining.min(len as u64);
+                Some(remaining - taken)
+            });
+        taken as usize
+    }
+}
+
+/// Which end of a pipe a [`LockPipe`] is, which decides what dropping it closes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Read,
+    Write,
+}
+
+/// One end of an in-memory pipe, like the ones between a [`WasiProcess`](crate::WasiProcess) and
+/// its stdio handles. A pair of them made with [`pair`](Self::pair) can be given to
+/// [`add_stdio_with_pipes`](crate::add_stdio_with_pipes) to connect processes to each other.
+/// Dropping the write end makes the read end get EOF once it's read everything, and dropping
+/// the read end makes writes fail with `BrokenPipe`, so an end isn't `Clone`: dropping a copy
+/// would close it for whoever else has one. `&LockPipe` is `AsyncRead` and `AsyncWrite`, for
+/// using an end from the host.
+///
+/// Every operation runs start to finish with the lock held, including checking whether it can go
+/// ahead and registering the waker if it can't. So the other end can't make progress in between,
+/// and whatever it does next sees the waker and wakes it. The pipe only keeps one waker per
+/// direction though, so only one task at a time can wait on reading it, and one on writing it.
+///
+/// The buffer is one contiguous slice, however many writes it came from, so a read into a big
+/// enough buffer gets everything that's buffered in a single poll, even an uninitialized one.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::future::poll_fn;
+/// use std::mem::MaybeUninit;
+/// use std::pin::Pin;
+/// use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+/// use wasi_process2::LockPipe;
+/// let (reader, writer) = LockPipe::pair(64 * 1024)?;
+/// let chunks = [vec![1; 1000], vec![2; 3000], vec![3; 5000]];
+/// for chunk in &chunks {
+///     (&writer).write_all(chunk).await?;
+/// }
+/// let mut storage = vec![MaybeUninit::uninit(); 64 * 1024];
+/// let mut buf = ReadBuf::uninit(&mut storage);
+/// // skip past the start of the buffer, like a caller that's already read something into it
+/// buf.put_slice(&[0; 100]);
+/// poll_fn(|cx| Pin::new(&mut &reader).poll_read(cx, &mut buf)).await?;
+/// assert_eq!(buf.filled().len(), 100 + 9000);
+/// assert_eq!(buf.filled()[100..], chunks.concat());
+///
+/// // nothing could ever be written to a pipe with no room in it
+/// assert!(LockPipe::pair(0).is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
 pub struct LockPipe {
     inner: Arc<Mutex<Pipe>>,
+    /// The end that's closed when this is dropped, if any.
+    end: Option<End>,
+    /// How many bytes the stdio pseudo-files collect before writing them to the pipe, or 0 to
+    /// write them right away, see
+    /// [`WasiProcess::coalesce_writes`](crate::WasiProcess::coalesce_writes).
+    coalesce: usize,
+    /// Whether stdin reads fail with `WouldBlock` rather than waiting when the pipe is empty, see
+    /// [`WasiProcess::nonblocking_stdin`](crate::WasiProcess::nonblocking_stdin).
+    nonblocking: bool,
+    /// Whether the process is paused, which the stdio pseudo-files wait out before they use the
+    /// pipe, see [`SpawnHandle::pause`](crate::SpawnHandle::pause).
+    paused: Option<watch::Receiver<bool>>,
 }
 
 impl Pipe {
     pub fn new(max_buf_size: usize) -> Self {
         Pipe {
             buffer: BytesMut::new(),
-            is_closed: false,
+            write_closed: false,
+            read_closed: false,
             max_buf_size,
             read_waker: None,
             write_waker: None,
+            bytes_written: 0,
+            bytes_read: 0,
+            max_len_seen: 0,
+            writes: 0,
+            allocations: 0,
+            output_limit: None,
+            streak: (End::Write, 0),
+            ring: false,
         }
     }
 
-    fn close(&mut self) {
-        self.is_closed = true;
+    /// Create a pipe that already holds `data` and is closed, so reading it gives `data` and then
+    /// EOF. `data` can be bigger than `max_buf_size`.
+    pub fn seeded(data: &[u8], max_buf_size: usize) -> Self {
+        let mut pipe = Pipe::new(max_buf_size);
+        pipe.extend(data);
+        pipe.record_write(data.len());
+        pipe.close_write();
+        pipe
+    }
+
+    fn metrics(&self) -> PipeMetrics {
+        PipeMetrics {
+            bytes_written: self.bytes_written,
+            bytes_read: self.bytes_read,
+            current_len: self.buffer.len(),
+            max_len_seen: self.max_len_seen,
+            writes: self.writes,
+            allocations: self.allocations,
+        }
+    }
+
+    /// Append `data` to the buffer, counting it if the buffer has to grow for it.
+    fn extend(&mut self, data: &[u8]) {
+        if !self.buffer.try_reclaim(data.len()) {
+            self.allocations += 1;
+        }
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Allocate the buffer up front, so writes don't have to grow it. `BytesMut` only moves the
+    /// buffered bytes back to the start of its allocation once at least as many have been read
+    /// as are left, so twice `max_buf_size` is what it takes for writes to always fit.
+    fn preallocate(&mut self) {
+        if self.write_closed {
+            return;
+        }
+        let capacity = self.max_buf_size.saturating_mul(2);
+        let additional = capacity.saturating_sub(self.buffer.len());
+        if !self.buffer.try_reclaim(additional) {
+            self.buffer.reserve(additional);
+        }
+    }
+
+    fn record_write(&mut self, len: usize) {
+        #[cfg(feature = "tracing")]
+        if crossed_trace_threshold(self.bytes_written, len) {
+            tracing::trace!(
+                bytes_written = self.bytes_written + len as u64,
+                "pipe written to"
+            );
+        }
+        self.bytes_written += len as u64;
+        self.max_len_seen = self.max_len_seen.max(self.buffer.len());
+        if len > 0 {
+            self.writes += 1;
+        }
+    }
+
+    /// Remove `len` bytes from the front of the buffer, after they've been read.
+    fn consume(&mut self, len: usize) {
+        self.buffer.advance(len);
+        self.record_read(len);
+    }
+
+    fn record_read(&mut self, len: usize) {
+        #[cfg(feature = "tracing")]
+        if crossed_trace_threshold(self.bytes_read, len) {
+            tracing::trace!(bytes_read = self.bytes_read + len as u64, "pipe read from");
+        }
+        self.bytes_read += len as u64;
+        if len > 0 {
+            // The passed `buf` might have been empty, don't wake up if
+            // no bytes have been moved.
+            if let Some(waker) = self.write_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Read without registering a waker; returns `WouldBlock` if the pipe is empty but still
+    /// open, and `Ok(0)` once it's closed.
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.has_remaining() {
+            let max = self.buffer.remaining().min(buf.len());
+            buf[..max].copy_from_slice(&self.buffer[..max]);
+            self.consume(max);
+            Ok(max)
+        } else if self.write_closed {
+            std::mem::take(&mut self.buffer);
+            Ok(0)
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+
+    /// Whether the write side is closed and everything has been read, so reads return EOF.
+    fn is_eof(&self) -> bool {
+        self.write_closed && !self.buffer.has_remaining()
+    }
+
+    /// Take everything that's currently in the buffer, whether or not the pipe is closed.
+    fn drain(&mut self) -> Bytes {
+        let len = self.buffer.len();
+        let data = self.buffer.split().freeze();
+        self.record_read(len);
+        data
+    }
+
+    /// Take up to `max` bytes from the front of the buffer once there's anything, without
+    /// copying them. Returns an empty `Bytes` once the pipe is closed and everything has been
+    /// read.
+    fn poll_read_bytes(&mut self, cx: &mut task::Context<'_>, max: usize) -> Poll<Bytes> {
+        if self.buffer.has_remaining() {
+            if self.yield_turn(End::Read, cx) {
+                return Poll::Pending;
+            }
+            let len = self.buffer.len().min(max);
+            let data = self.buffer.split_to(len).freeze();
+            self.record_read(len);
+            Poll::Ready(data)
+        } else if self.write_closed {
+            std::mem::take(&mut self.buffer);
+            Poll::Ready(Bytes::new())
+        } else {
+            self.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Poll for there being something in the buffer, registering the read waker if there isn't.
+    /// Unlike reading, this stays pending at EOF.
+    fn poll_has_data(&mut self, cx: &mut task::Context<'_>) -> Poll<()> {
+        if self.buffer.has_remaining() {
+            Poll::Ready(())
+        } else {
+            self.read_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    /// Copy bytes from the front of the buffer without consuming them.
+    fn peek(&self, buf: &mut [u8]) -> usize {
+        let n = self.buffer.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.buffer[..n]);
+        n
+    }
+
+    /// Write without registering a waker; returns `WouldBlock` if the pipe is full.
+    fn try_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_closed() {
+            return Err(std::io::ErrorKind::BrokenPipe.into());
+        }
+        if self.ring {
+            return self.write_ring(buf);
+        }
+        let avail = self.max_buf_size.saturating_sub(self.buffer.len());
+        if avail == 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+
+        // possibly a short write, which is up to the caller to finish, like with any writer
+        let len = self.take_output_budget(buf.len().min(avail))?;
+        self.extend(&buf[..len]);
+        self.record_write(len);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        Ok(len)
+    }
+
+    /// Write all of `buf`, dropping as much from the front of the buffer as it takes for the
+    /// buffer to hold only the last `max_buf_size` bytes ever written.
+    fn write_ring(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.take_output_budget(buf.len())?;
+        // only the end of a write that's bigger than the whole buffer could be kept anyway
+        let tail = &buf[len.saturating_sub(self.max_buf_size)..len];
+        let overflow = (self.buffer.len() + tail.len()).saturating_sub(self.max_buf_size);
+        self.buffer.advance(overflow);
+        self.extend(tail);
+        self.record_write(len);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        Ok(len)
+    }
+
+    /// Write as much of `data` as fits, taking it off the front of `data`. If the buffer is
+    /// empty and all of `data` fits, `data` becomes the buffer rather than being copied into it,
+    /// as long as nothing else holds on to its memory.
+    fn poll_write_bytes(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        data: &mut Bytes,
+    ) -> Poll<io::Result<usize>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let avail = self.max_buf_size.saturating_sub(self.buffer.len());
+        if avail == 0 {
+            self.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        if self.yield_turn(End::Write, cx) {
+            return Poll::Pending;
+        }
+        let len = self.take_output_budget(data.len().min(avail))?;
+        let chunk = data.split_to(len);
+        if self.buffer.is_empty() && data.is_empty() {
+            match chunk.try_into_mut() {
+                Ok(chunk) => self.buffer = chunk,
+                Err(chunk) => self.extend(&chunk),
+            }
+        } else {
+            self.extend(&chunk);
+        }
+        self.record_write(len);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    /// Get how many bytes can be written without waiting, registering the write waker if that's
+    /// none. A closed pipe is always ready, so that the next write can report it.
+    fn poll_capacity(&mut self, cx: &mut task::Context<'_>) -> Poll<usize> {
+        let avail = self.max_buf_size.saturating_sub(self.buffer.len());
+        if avail == 0 && !self.is_closed() {
+            self.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(avail)
+    }
+
+    /// Change the maximum size of the buffer. Shrinking it below the amount of buffered bytes
+    /// keeps them, it just stops writes until enough have been read, unless the pipe is a ring,
+    /// which drops the oldest ones right away.
+    fn set_max_buf_size(&mut self, max_buf_size: usize) {
+        self.max_buf_size = max_buf_size;
+        if self.ring {
+            let overflow = self.buffer.len().saturating_sub(max_buf_size);
+            self.buffer.advance(overflow);
+        }
+        if self.buffer.len() < max_buf_size {
+            if let Some(waker) = self.write_waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn snapshot(&self) -> PipeSnapshot {
+        PipeSnapshot {
+            data: self.buffer.to_vec(),
+            is_closed: self.write_closed,
+            max_buf_size: self.max_buf_size,
+        }
+    }
+
+    /// Replace the contents of the pipe with `snapshot`, counting its data as written. A pipe
+    /// that's already closed stays closed, since its writer is gone.
+    fn restore(&mut self, snapshot: &PipeSnapshot) {
+        self.buffer.clear();
+        self.extend(&snapshot.data);
+        self.record_write(snapshot.data.len());
+        self.set_max_buf_size(snapshot.max_buf_size);
+        if snapshot.is_closed || self.write_closed {
+            self.close_write();
+        } else if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Count an operation by `end`. After [`FAIR_STREAK`] of them in a row, this wakes the other
+    /// end and `cx`'s task and returns `true`, and the caller should return `Pending` once, so a
+    /// task that's busy with one end can't keep the other from running.
+    fn yield_turn(&mut self, end: End, cx: &mut task::Context<'_>) -> bool {
+        if self.streak.0 != end {
+            self.streak = (end, 0);
+        }
+        self.streak.1 += 1;
+        if self.streak.1 <= FAIR_STREAK {
+            return false;
+        }
+        self.streak.1 = 0;
+        let other = match end {
+            End::Read => self.write_waker.take(),
+            End::Write => self.read_waker.take(),
+        };
+        if let Some(waker) = other {
+            waker.wake();
+        }
+        cx.waker().wake_by_ref();
+        true
+    }
+
+    /// Take up to `len` bytes from the output budget, if there is one. Once it's used up, the
+    /// pipe is closed and the write fails with `BrokenPipe`.
+    fn take_output_budget(&mut self, len: usize) -> io::Result<usize> {
+        let limit = match &self.output_limit {
+            Some(limit) => limit.clone(),
+            None => return Ok(len),
+        };
+        let taken = limit.take(len);
+        if taken == 0 && len > 0 {
+            limit.exceeded.store(true, Ordering::Release);
+            self.close();
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        Ok(taken)
+    }
+
+    /// Whether either end has been closed, so nothing more can be written.
+    fn is_closed(&self) -> bool {
+        self.write_closed || self.read_closed
+    }
+
+    /// Close the write side: the reader gets what's left in the buffer and then EOF. The buffer
+    /// is only let go of here if it's empty; otherwise the reader is woken to drain it, and reads
+    /// only report EOF once it has.
+    fn close_write(&mut self) {
+        self.trace_close();
+        self.write_closed = true;
         if !self.buffer.has_remaining() {
             std::mem::take(&mut self.buffer);
         }
+        self.wake_both();
+    }
+
+    /// Close the read side: the writer gets `BrokenPipe` instead of waiting for room that will
+    /// never come.
+    fn close_read(&mut self) {
+        self.trace_close();
+        self.read_closed = true;
+        self.wake_both();
+    }
+
+    /// Close the read side and throw away whatever's buffered, for a reader that's done with
+    /// the pipe.
+    fn discard(&mut self) {
+        std::mem::take(&mut self.buffer);
+        self.close_read();
+    }
+
+    /// Close both sides, e.g. when the process is cancelled.
+    fn close(&mut self) {
+        self.close_write();
+        self.read_closed = true;
+    }
+
+    fn trace_close(&self) {
+        #[cfg(feature = "tracing")]
+        if !self.is_closed() {
+            tracing::debug!(
+                bytes_written = self.bytes_written,
+                bytes_left = self.buffer.len(),
+                "pipe closed"
+            );
+        }
+    }
+
+    fn wake_both(&mut self) {
         if let Some(waker) = self.read_waker.take() {
             waker.wake();
         }
@@ -72,18 +691,16 @@ impl AsyncRead for Pipe {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         if self.buffer.has_remaining() {
+            if self.yield_turn(End::Read, cx) {
+                return Poll::Pending;
+            }
+            // both the buffer and the unfilled part of `buf` are contiguous, so this one copy
+            // moves as much as can be moved
             let max = self.buffer.remaining().min(buf.remaining());
             buf.put_slice(&self.buffer[..max]);
-            self.buffer.advance(max);
-            if max > 0 {
-                // The passed `buf` might have been empty, don't wake up if
-                // no bytes have been moved.
-                if let Some(waker) = self.write_waker.take() {
-                    waker.wake();
-                }
-            }
+            self.consume(max);
             Poll::Ready(Ok(()))
-        } else if self.is_closed {
+        } else if self.write_closed {
             std::mem::take(&mut self.buffer);
             Poll::Ready(Ok(()))
         } else {
@@ -99,37 +716,276 @@ impl AsyncWrite for Pipe {
         cx: &mut task::Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        if self.is_closed {
+        // checked before anything else, so a writer that's been waiting for room finds out as
+        // soon as it's woken that none is coming
+        if self.is_closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let has_room = self.buffer.len() < self.max_buf_size;
+        if has_room && self.yield_turn(End::Write, cx) {
+            return Poll::Pending;
+        }
+        match self.try_write(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                self.write_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if self.is_closed() {
             return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
         }
-        let avail = self.max_buf_size - self.buffer.len();
+        if self.ring {
+            let buf = bufs
+                .iter()
+                .find(|buf| !buf.is_empty())
+                .map_or(&[][..], |buf| buf);
+            return Poll::Ready(self.write_ring(buf));
+        }
+        let avail = self.max_buf_size.saturating_sub(self.buffer.len());
         if avail == 0 {
             self.write_waker = Some(cx.waker().clone());
             return Poll::Pending;
         }
+        if self.yield_turn(End::Write, cx) {
+            return Poll::Pending;
+        }
+        let total = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        let mut avail = match self.take_output_budget(total.min(avail)) {
+            Ok(avail) => avail,
+            Err(e) => return Poll::Ready(Err(e)),
+        };
 
-        let len = buf.len().min(avail);
-        self.buffer.extend_from_slice(&buf[..len]);
+        let mut written = 0;
+        for buf in bufs {
+            if avail == 0 {
+                break;
+            }
+            let len = buf.len().min(avail);
+            self.extend(&buf[..len]);
+            avail -= len;
+            written += len;
+        }
+        self.record_write(written);
         if let Some(waker) = self.read_waker.take() {
             waker.wake();
         }
-        Poll::Ready(Ok(len))
+        Poll::Ready(Ok(written))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        // There's nothing to write out, but make sure a parked reader gets to see what's been
+        // written so far.
+        if self.buffer.has_remaining() {
+            if let Some(waker) = self.read_waker.take() {
+                waker.wake();
+            }
+        }
         Poll::Ready(Ok(()))
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<io::Result<()>> {
-        self.close();
+        self.close_write();
         Poll::Ready(Ok(()))
     }
 }
 
 impl LockPipe {
-    pub fn new(max_buf_size: usize) -> Self {
+    /// Create a pipe, returning its read end and its write end. Fails with
+    /// [`NewError::ZeroBufSize`](crate::NewError::ZeroBufSize) if `max_buf_size` is 0, since
+    /// nothing could ever be written to it.
+    pub fn pair(max_buf_size: usize) -> Result<(Self, Self), crate::NewError> {
+        crate::check_buf_size("pipe", max_buf_size)?;
+        Ok(Self::new_pair(max_buf_size))
+    }
+
+    /// Create a pipe like [`pair`](Self::pair), with a size that's already been checked.
+    pub(crate) fn new_pair(max_buf_size: usize) -> (Self, Self) {
         let inner = Arc::new(Mutex::new(Pipe::new(max_buf_size)));
-        Self { inner }
+        let reader = Self {
+            inner: inner.clone(),
+            end: Some(End::Read),
+            coalesce: 0,
+            nonblocking: false,
+            paused: None,
+        };
+        let writer = Self {
+            inner,
+            end: Some(End::Write),
+            coalesce: 0,
+            nonblocking: false,
+            paused: None,
+        };
+        (reader, writer)
+    }
+
+    /// Create the read end of a pipe whose write end is already closed, see [`Pipe::seeded`].
+    pub(crate) fn seeded(data: &[u8], max_buf_size: usize) -> Self {
+        let inner = Arc::new(Mutex::new(Pipe::seeded(data, max_buf_size)));
+        Self {
+            inner,
+            end: Some(End::Read),
+            coalesce: 0,
+            nonblocking: false,
+            paused: None,
+        }
+    }
+
+    /// Get another handle to the same end of the pipe, which closes it too when it's dropped,
+    /// for running the module with its own copy of its ends.
+    pub(crate) fn clone_end(&self) -> Self {
+        let mut pipe = self.handle();
+        pipe.end = self.end;
+        pipe
+    }
+
+    /// Whether `other` is a handle to the same pipe.
+    pub(crate) fn same_pipe(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Get another handle to the same pipe that doesn't close either end when it's dropped.
+    pub(crate) fn handle(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            end: None,
+            coalesce: self.coalesce,
+            nonblocking: self.nonblocking,
+            paused: self.paused.clone(),
+        }
+    }
+
+    pub(crate) fn coalesce(&self) -> usize {
+        self.coalesce
+    }
+
+    pub(crate) fn set_coalesce(&mut self, threshold: usize) {
+        self.coalesce = threshold;
+    }
+
+    pub(crate) fn nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    pub(crate) fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    pub(crate) fn paused(&self) -> Option<&watch::Receiver<bool>> {
+        self.paused.as_ref()
+    }
+
+    pub(crate) fn set_paused(&mut self, paused: watch::Receiver<bool>) {
+        self.paused = Some(paused);
+    }
+
+    pub(crate) fn metrics(&self) -> PipeMetrics {
+        self.inner.lock().metrics()
+    }
+
+    pub(crate) fn preallocate(&self) {
+        self.inner.lock().preallocate()
+    }
+
+    pub(crate) fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.lock().try_read(buf)
+    }
+
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.lock().try_write(buf)
+    }
+
+    pub(crate) fn peek(&self, buf: &mut [u8]) -> usize {
+        self.inner.lock().peek(buf)
+    }
+
+    pub(crate) fn drain(&self) -> Bytes {
+        self.inner.lock().drain()
+    }
+
+    pub(crate) fn poll_read_bytes(&self, cx: &mut task::Context<'_>, max: usize) -> Poll<Bytes> {
+        self.inner.lock().poll_read_bytes(cx, max)
+    }
+
+    pub(crate) fn poll_has_data(&self, cx: &mut task::Context<'_>) -> Poll<()> {
+        self.inner.lock().poll_has_data(cx)
+    }
+
+    pub(crate) fn close(&self) {
+        self.inner.lock().close()
+    }
+
+    pub(crate) fn close_write(&self) {
+        self.inner.lock().close_write()
+    }
+
+    pub(crate) fn discard(&self) {
+        self.inner.lock().discard()
+    }
+
+    pub(crate) fn set_output_limit(&self, limit: Arc<OutputLimit>) {
+        self.inner.lock().output_limit = Some(limit);
+    }
+
+    pub(crate) fn snapshot(&self) -> PipeSnapshot {
+        self.inner.lock().snapshot()
+    }
+
+    pub(crate) fn restore(&self, snapshot: &PipeSnapshot) {
+        self.inner.lock().restore(snapshot)
+    }
+
+    /// Whether the write end has been closed, so nothing more will be written.
+    pub(crate) fn is_write_closed(&self) -> bool {
+        self.inner.lock().write_closed
+    }
+
+    /// Whether both ends have been closed, like by [`close`](Self::close).
+    pub(crate) fn is_fully_closed(&self) -> bool {
+        let pipe = self.inner.lock();
+        pipe.read_closed && pipe.write_closed
+    }
+
+    pub(crate) fn is_eof(&self) -> bool {
+        self.inner.lock().is_eof()
+    }
+
+    pub(crate) fn max_buf_size(&self) -> usize {
+        self.inner.lock().max_buf_size
+    }
+
+    pub(crate) fn set_max_buf_size(&self, max_buf_size: usize) {
+        self.inner.lock().set_max_buf_size(max_buf_size)
+    }
+
+    /// Make the pipe a ring that keeps only the last `bytes` written, see [`Pipe::write_ring`].
+    pub(crate) fn set_ring(&self, bytes: usize) {
+        let mut pipe = self.inner.lock();
+        pipe.ring = true;
+        pipe.set_max_buf_size(bytes);
+    }
+
+    pub(crate) fn poll_write_bytes(
+        &self,
+        cx: &mut task::Context<'_>,
+        data: &mut Bytes,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.lock().poll_write_bytes(cx, data)
+    }
+
+    pub(crate) fn poll_capacity(&self, cx: &mut task::Context<'_>) -> Poll<usize> {
+        self.inner.lock().poll_capacity(cx)
     }
 }
 
@@ -152,6 +1008,18 @@ impl AsyncWrite for &'_ LockPipe {
         Pin::new(&mut *self.inner.lock()).poll_write(cx, buf)
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner.lock()).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
     fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
         Pin::new(&mut *self.inner.lock()).poll_flush(cx)
     }
@@ -164,6 +1032,10 @@ impl AsyncWrite for &'_ LockPipe {
 impl Drop for LockPipe {
     fn drop(&mut self) {
         // notify the other side of the closure
-        self.inner.lock().close();
+        match self.end {
+            Some(End::Read) => self.inner.lock().close_read(),
+            Some(End::Write) => self.inner.lock().close_write(),
+            None => {}
+        }
     }
 }
@@ -0,0 +1,142 @@
+//! Connecting processes like a shell pipeline, see [`pipeline`].
+
+use tokio::io;
+
+use super::{SpawnError, SpawnHandle, WasiProcess, WasiStdin, WasiStdout};
+
+/// Connect each process's stdout to the next one's stdin, like `a | b | c` in a shell, and spawn
+/// all of them. This has to be called from within a tokio runtime.
+///
+/// The stdin of the first process and the stdout of the last one are left for the caller in the
+/// returned [`Pipeline`]. When a process's stdout hits EOF, the next one's stdin is closed, and
+/// when a process stops reading its stdin, writes to the previous one's stdout fail with a broken
+/// pipe error. Stderr that hasn't been taken from a process is read and discarded, so it can't
+/// hold the process up; take it beforehand to read it.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use wasi_process2::WasiProcessBuilder;
+/// # let store = wasmer::Store::default();
+/// # let engine = store.engine().clone();
+/// // copies stdin to stdout until EOF, then writes "END\n"
+/// let module = wasmer::Module::new(&store, include_str!("../echo.wat"))?;
+/// let cat = WasiProcessBuilder::new("cat", module).build(store)?;
+///
+/// // copies stdin to stdout in uppercase
+/// let upper = r#"(module
+///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start") (local $i i32) (local $c i32)
+///         (loop $copy
+///             (i32.store (i32.const 0) (i32.const 64))
+///             (i32.store (i32.const 4) (i32.const 1024))
+///             (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+///             (if (i32.load (i32.const 8))
+///                 (then
+///                     (local.set $i (i32.const 0))
+///                     (loop $upper
+///                         (local.set $c (i32.load8_u (i32.add (i32.const 64) (local.get $i))))
+///                         (if (i32.lt_u (i32.sub (local.get $c) (i32.const 97)) (i32.const 26))
+///                             (then (i32.store8 (i32.add (i32.const 64) (local.get $i))
+///                                 (i32.sub (local.get $c) (i32.const 32)))))
+///                         (local.set $i (i32.add (local.get $i) (i32.const 1)))
+///                         (br_if $upper (i32.lt_u (local.get $i) (i32.load (i32.const 8)))))
+///                     (i32.store (i32.const 4) (i32.load (i32.const 8)))
+///                     (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+///                     (br $copy))))))"#;
+/// # let store = wasmer::Store::new(engine.clone());
+/// let module = wasmer::Module::new(&store, upper)?;
+/// let upper = WasiProcessBuilder::new("upper", module).build(store)?;
+///
+/// // reads stdin until EOF, and exits with how many uppercase letters were in it
+/// let sink = r#"(module
+///     (import "wasi_unstable" "fd_read" (func $fd_read (param i32 i32 i32 i32) (result i32)))
+///     (import "wasi_unstable" "proc_exit" (func $proc_exit (param i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start") (local $i i32) (local $count i32)
+///         (loop $read
+///             (i32.store (i32.const 0) (i32.const 64))
+///             (i32.store (i32.const 4) (i32.const 1024))
+///             (drop (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)))
+///             (if (i32.load (i32.const 8))
+///                 (then
+///                     (local.set $i (i32.const 0))
+///                     (loop $scan
+///                         (if (i32.lt_u
+///                                 (i32.sub (i32.load8_u (i32.add (i32.const 64) (local.get $i)))
+///                                     (i32.const 65))
+///                                 (i32.const 26))
+///                             (then (local.set $count (i32.add (local.get $count) (i32.const 1)))))
+///                         (local.set $i (i32.add (local.get $i) (i32.const 1)))
+///                         (br_if $scan (i32.lt_u (local.get $i) (i32.load (i32.const 8)))))
+///                     (br $read))))
+///         (call $proc_exit (local.get $count)))
+///     (func (param i64)))"#;
+/// # let store = wasmer::Store::new(engine);
+/// let module = wasmer::Module::new(&store, sink)?;
+/// let sink = WasiProcessBuilder::new("sink", module).build(store)?;
+///
+/// let mut pipeline = wasi_process2::pipeline(vec![cat, upper, sink]);
+/// let mut stdin = pipeline.stdin.take().unwrap();
+/// stdin.write_all(b"hello\n").await?;
+/// drop(stdin);
+/// let mut out = Vec::new();
+/// pipeline.stdout.take().unwrap().read_to_end(&mut out).await?;
+/// assert!(out.is_empty());
+/// // "HELLO\nEND\n" has 8 uppercase letters
+/// let statuses = pipeline.wait().await;
+/// assert!(matches!(statuses[..], [Ok(0), Ok(0), Ok(8)]));
+/// # Ok(())
+/// # }
+/// ```
+pub fn pipeline(processes: Vec<WasiProcess>) -> Pipeline {
+    let mut pipeline = Pipeline {
+        stdin: None,
+        stdout: None,
+        stages: Vec::with_capacity(processes.len()),
+    };
+    for (i, mut process) in processes.into_iter().enumerate() {
+        let stdin = process.stdin.take();
+        if i == 0 {
+            pipeline.stdin = stdin;
+        } else if let (Some(mut from), Some(mut to)) = (pipeline.stdout.take(), stdin) {
+            // dropping both ends afterwards passes EOF on to the next process, or a broken pipe
+            // back to the previous one
+            tokio::spawn(async move {
+                let _ = io::copy(&mut from, &mut to).await;
+            });
+        }
+        if let Some(mut stderr) = process.stderr.take() {
+            tokio::spawn(async move {
+                let _ = io::copy(&mut stderr, &mut io::sink()).await;
+            });
+        }
+        pipeline.stdout = process.stdout.take();
+        pipeline.stages.push(process.spawn());
+    }
+    pipeline
+}
+
+/// A set of connected processes, from [`pipeline`].
+pub struct Pipeline {
+    /// The stdin of the first process
+    pub stdin: Option<WasiStdin>,
+    /// The stdout of the last process
+    pub stdout: Option<WasiStdout>,
+    stages: Vec<SpawnHandle>,
+}
+
+impl Pipeline {
+    /// Wait for all of the processes to finish, and get their exit codes like
+    /// [`Output::status`](super::Output), or the errors they failed with, in order.
+    pub async fn wait(self) -> Vec<Result<i32, SpawnError>> {
+        let mut statuses = Vec::with_capacity(self.stages.len());
+        for stage in self.stages {
+            statuses.push(stage.await.map(|status| status.code()));
+        }
+        statuses
+    }
+}
@@ -1,48 +1,193 @@
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
 use std::io::{prelude::*, SeekFrom};
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
-use tokio::runtime::Handle;
 use wasmer_wasi::{WasiFile, WasiFsError};
 
-use super::{STDERR, STDIN, STDOUT};
+use super::pipe::LockPipe;
+use super::{Direction, STDIO};
+
+/// Run `f` with the pipe for `fd` in the current process, or fail with a broken pipe error if
+/// there isn't one. Outside of a process, e.g. when a module's export is called directly rather
+/// than through a [`WasiProcess`](super::WasiProcess), this fails with an error the module sees
+/// as `EIO` rather than panicking.
+fn with_pipe<R>(fd: u32, f: impl FnOnce(&LockPipe) -> io::Result<R>) -> io::Result<R> {
+    STDIO
+        .try_with(|stdio| match stdio.pipes.get(&fd) {
+            Some(pipe) => {
+                wait_while_paused(pipe);
+                f(pipe)
+            }
+            None => Err(io::ErrorKind::BrokenPipe.into()),
+        })
+        .unwrap_or_else(|_| {
+            Err(io::Error::other(
+                "stdio was used outside of a wasi process; call the module's functions through \
+                 a WasiProcess, or use WasiProcess::scope_stdio",
+            ))
+        })
+}
+
+/// Block the current thread for as long as the process is paused, see
+/// [`SpawnHandle::pause`](super::SpawnHandle::pause). A pipe that's been closed by killing the
+/// process, or it timing out, doesn't wait, so a module that's paused again after that can
+/// still finish.
+fn wait_while_paused(pipe: &LockPipe) {
+    if let Some(paused) = pipe.paused() {
+        // this is on every stdio call, so only set up the wait when there's something to wait for
+        if *paused.borrow() && !pipe.is_fully_closed() {
+            // the process is gone if there's nothing left to resume it, so don't wait for that
+            let _ = block_on(paused.clone().wait_for(|paused| !paused));
+        }
+    }
+}
+
+thread_local! {
+    /// What's been written to stdout and stderr without being put in their pipes yet, by fd, see
+    /// [`WasiProcess::coalesce_writes`](super::WasiProcess::coalesce_writes). Only one module
+    /// runs on a thread at a time, and this is flushed once it returns.
+    static STAGED: RefCell<HashMap<u32, Vec<u8>>> = RefCell::new(HashMap::new());
+}
+
+/// Write `buf` to the pipe for `fd`, or if the pipe coalesces writes, collect it until there's
+/// enough to be worth taking the pipe's lock for.
+fn write_stdio(fd: u32, buf: &[u8]) -> io::Result<usize> {
+    with_pipe(fd, |mut pipe| {
+        let threshold = pipe.coalesce();
+        if threshold == 0 {
+            return block_on(pipe.write(buf));
+        }
+        let full = STAGED.with(|staged| {
+            let mut staged = staged.borrow_mut();
+            let staged = staged.entry(fd).or_default();
+            staged.extend_from_slice(buf);
+            (staged.len() >= threshold).then(|| std::mem::take(staged))
+        });
+        if let Some(data) = full {
+            block_on(pipe.write_all(&data))?;
+        }
+        Ok(buf.len())
+    })
+}
+
+/// Write out everything [`write_stdio`] has collected, e.g. once the module returns or before it
+/// waits for input. Errors are ignored, since the writes they belong to have already succeeded
+/// as far as the module knows.
+pub(crate) fn flush_staged() {
+    let staged = STAGED.with(|staged| std::mem::take(&mut *staged.borrow_mut()));
+    for (fd, data) in staged {
+        if !data.is_empty() {
+            let _ = with_pipe(fd, |mut pipe| block_on(pipe.write_all(&data)));
+        }
+    }
+}
+
+/// Block the current thread until `f` is done. The pipes only need their wakers to make progress,
+/// so this doesn't need a tokio runtime, and it works even within one, like for a
+/// [`LocalWasiProcess`](super::LocalWasiProcess). Unlike `Handle::block_on`, it doesn't hold up
+/// the runtime's scheduler while it waits, so the tasks on the host end of the pipe can always
+/// run, even with few worker threads.
+pub(crate) fn block_on<F: Future>(f: F) -> F::Output {
+    struct Unpark(Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut f = pin!(f);
+    loop {
+        if let Poll::Ready(out) = f.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
+
+/// The error for seeking a stdio stream, which is a pipe, so it can't be seeked.
+fn not_seekable(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotSeekable, format!("can not seek {}", name))
+}
 
 /// The stdin pseudo-file for wasi processes.
+///
+/// None of the stdio pseudo-files can be seeked; trying fails with an `ErrorKind::NotSeekable`
+/// error, like seeking a pipe on the host. A module calling `fd_seek` on stdio gets `EACCES`
+/// from wasmer-wasi before it gets that far, since the stdio fds don't have the seek right, so it
+/// can still tell that the fd isn't seekable apart from an I/O error.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::io::{ErrorKind, Seek, SeekFrom};
+/// use tokio::io::AsyncReadExt;
+/// use wasi_process2::{Stdin, WasiProcessBuilder};
+/// let err = Stdin.seek(SeekFrom::Start(0)).unwrap_err();
+/// assert_eq!(err.kind(), ErrorKind::NotSeekable);
+///
+/// # let store = wasmer::Store::default();
+/// // seeks to the end of stdin and writes the errno it got as a single byte
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_seek" (func $fd_seek (param i32 i64 i32 i32) (result i32)))
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (func (export "_start")
+///         (i32.store8 (i32.const 100) (call $fd_seek (i32.const 0) (i64.const 0) (i32.const 2) (i32.const 16)))
+///         (i32.store (i32.const 0) (i32.const 100))
+///         (i32.store (i32.const 4) (i32.const 1))
+///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+///     (func (param i64)))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut wasi = WasiProcessBuilder::new("seek", module).build(store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn();
+/// let mut errno = Vec::new();
+/// stdout.read_to_end(&mut errno).await?;
+/// // EACCES rather than the generic EIO
+/// assert_eq!(errno, [2]);
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Stdin;
 impl Read for Stdin {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        STDIN.with(|stdin| Handle::current().block_on((&*stdin).read(buf)))
+        // anything the module's written should be out before it waits for an answer to it
+        flush_staged();
+        with_pipe(0, |mut stdin| {
+            if stdin.nonblocking() {
+                stdin.try_read(buf)
+            } else {
+                block_on(stdin.read(buf))
+            }
+        })
     }
 }
 impl Seek for Stdin {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        Err(io::Error::new(io::ErrorKind::Other, "can not seek stdin"))
+        Err(not_seekable("stdin"))
     }
 }
 impl Write for Stdin {
     fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not write to stdin",
-        ))
+        Err(io::Error::other("can not write to stdin"))
     }
     fn flush(&mut self) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not write to stdin",
-        ))
+        Err(io::Error::other("can not write to stdin"))
     }
     fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not write to stdin",
-        ))
+        Err(io::Error::other("can not write to stdin"))
     }
     fn write_fmt(&mut self, _fmt: ::std::fmt::Arguments) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not write to stdin",
-        ))
+        Err(io::Error::other("can not write to stdin"))
     }
 }
 
@@ -68,49 +213,78 @@ impl WasiFile for Stdin {
     }
 
     fn bytes_available(&self) -> Result<usize, WasiFsError> {
-        Ok(0)
+        Ok(with_pipe(0, |stdin| Ok(stdin.metrics().current_len)).unwrap_or(0))
     }
 }
 
 /// The stdout pseudo-file for wasi processes.
+///
+/// Like the other stdio pseudo-files, it shows up to the module as a character device without
+/// the rights to seek or tell, in both `fd_fdstat_get` and `fd_filestat_get`. That's what
+/// `isatty` checks for, so modules treat their stdio as interactive, e.g. flushing stdout after
+/// every line rather than only once their buffer fills up.
+///
+/// # Examples
+/// ```
+/// # #[tokio::main] async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # use tokio::io::AsyncReadExt;
+/// # use wasi_process2::WasiProcessBuilder;
+/// # let store = wasmer::Store::default();
+/// let wat = r#"(module
+///     (import "wasi_unstable" "fd_fdstat_get" (func $fd_fdstat_get (param i32 i32) (result i32)))
+///     (import "wasi_unstable" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+///     (memory (export "memory") 1)
+///     (data (i32.const 64) "tty\n")
+///     (data (i32.const 68) "file\n")
+///     ;; isatty the way wasi-libc does it: a character device that can't seek or tell
+///     (func $isatty (param $fd i32) (result i32)
+///         (drop (call $fd_fdstat_get (local.get $fd) (i32.const 32)))
+///         (i32.and
+///             (i32.eq (i32.load8_u (i32.const 32)) (i32.const 2))
+///             (i64.eqz (i64.and (i64.load (i32.const 40)) (i64.const 0x24)))))
+///     (func (export "_start")
+///         (if (call $isatty (i32.const 1))
+///             (then (i32.store (i32.const 0) (i32.const 64)) (i32.store (i32.const 4) (i32.const 4)))
+///             (else (i32.store (i32.const 0) (i32.const 68)) (i32.store (i32.const 4) (i32.const 5))))
+///         (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))"#;
+/// let module = wasmer::Module::new(&store, wat)?;
+/// let mut wasi = WasiProcessBuilder::new("isatty", module).build(store)?;
+/// let mut stdout = wasi.stdout.take().unwrap();
+/// wasi.spawn().await?;
+/// let mut out = String::new();
+/// stdout.read_to_string(&mut out).await?;
+/// assert_eq!(out, "tty\n");
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Stdout;
 impl Read for Stdout {
     fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stdout",
-        ))
+        Err(io::Error::other("can not read from stdout"))
     }
     fn read_to_end(&mut self, _buf: &mut Vec<u8>) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stdout",
-        ))
+        Err(io::Error::other("can not read from stdout"))
     }
     fn read_to_string(&mut self, _buf: &mut String) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stdout",
-        ))
+        Err(io::Error::other("can not read from stdout"))
     }
     fn read_exact(&mut self, _buf: &mut [u8]) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stdout",
-        ))
+        Err(io::Error::other("can not read from stdout"))
     }
 }
 impl Seek for Stdout {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        Err(io::Error::new(io::ErrorKind::Other, "can not seek stdout"))
+        Err(not_seekable("stdout"))
     }
 }
 impl Write for Stdout {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        STDOUT.with(|stdout| Handle::current().block_on((&*stdout).write(buf)))
+        write_stdio(1, buf)
     }
     fn flush(&mut self) -> io::Result<()> {
+        // wasmer flushes after every `fd_write`, so writing out what's been collected here would
+        // defeat the point of collecting it
         Ok(())
     }
 }
@@ -145,38 +319,26 @@ impl WasiFile for Stdout {
 pub struct Stderr;
 impl Read for Stderr {
     fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stderr",
-        ))
+        Err(io::Error::other("can not read from stderr"))
     }
     fn read_to_end(&mut self, _buf: &mut Vec<u8>) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stderr",
-        ))
+        Err(io::Error::other("can not read from stderr"))
     }
     fn read_to_string(&mut self, _buf: &mut String) -> io::Result<usize> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stderr",
-        ))
+        Err(io::Error::other("can not read from stderr"))
     }
     fn read_exact(&mut self, _buf: &mut [u8]) -> io::Result<()> {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "can not read from stderr",
-        ))
+        Err(io::Error::other("can not read from stderr"))
     }
 }
 impl Seek for Stderr {
     fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        Err(io::Error::new(io::ErrorKind::Other, "can not seek stderr"))
+        Err(not_seekable("stderr"))
     }
 }
 impl Write for Stderr {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        STDERR.with(|stderr| Handle::current().block_on((&*stderr).write(buf)))
+        write_stdio(2, buf)
     }
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
@@ -207,3 +369,179 @@ impl WasiFile for Stderr {
         Err(WasiFsError::InvalidInput)
     }
 }
+
+/// A pseudo-file for a stdio stream that's been set to [`Stdio::Null`](super::Stdio::Null):
+/// writes always succeed and are dropped, and reads get EOF.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NullPipe;
+impl Read for NullPipe {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+impl Seek for NullPipe {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+impl Write for NullPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WasiFile for NullPipe {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), WasiFsError> {
+        Err(WasiFsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), WasiFsError> {
+        Ok(())
+    }
+
+    fn bytes_available(&self) -> Result<usize, WasiFsError> {
+        Ok(0)
+    }
+}
+
+/// The pseudo-file for an extra pipe, see [`add_pipes`](super::add_pipes).
+#[derive(Debug)]
+pub(crate) struct PipeFile {
+    pub fd: u32,
+    pub direction: Direction,
+}
+impl Read for PipeFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.direction != Direction::Read {
+            return Err(io::Error::other("can not read from a write pipe"));
+        }
+        with_pipe(self.fd, |mut pipe| block_on(pipe.read(buf)))
+    }
+}
+impl Seek for PipeFile {
+    // wasmer seeks to the fd's offset before every read or write on a non-stdio file, so this
+    // has to succeed
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+impl Write for PipeFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.direction != Direction::Write {
+            return Err(io::Error::other("can not write to a read pipe"));
+        }
+        with_pipe(self.fd, |mut pipe| block_on(pipe.write(buf)))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WasiFile for PipeFile {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), WasiFsError> {
+        Err(WasiFsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), WasiFsError> {
+        Ok(())
+    }
+
+    fn bytes_available(&self) -> Result<usize, WasiFsError> {
+        Ok(0)
+    }
+}
+
+/// The pseudo-file for a stdio stream that's been given its own pipe, see
+/// [`add_stdio_with_pipes`](super::add_stdio_with_pipes). Unlike the other pseudo-files, it
+/// uses the pipe directly rather than looking it up in the current process, but it lets the
+/// process know about it, so that it's closed along with the process's own pipes, and waits
+/// while the process is paused like they do.
+#[derive(Debug)]
+pub(crate) struct SharedPipe {
+    pub pipe: LockPipe,
+    pub direction: Direction,
+}
+impl SharedPipe {
+    /// Run `f` with the pipe, after registering it with the current process, if there is one,
+    /// and waiting for as long as that's paused.
+    fn with_pipe<R>(&self, f: impl FnOnce(&LockPipe) -> io::Result<R>) -> io::Result<R> {
+        if let Ok(registered) = STDIO.try_with(|stdio| stdio.shared.register(&self.pipe)) {
+            wait_while_paused(&registered);
+        }
+        f(&self.pipe)
+    }
+}
+impl Read for SharedPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.direction != Direction::Read {
+            return Err(io::Error::other("can not read from a write pipe"));
+        }
+        self.with_pipe(|mut pipe| block_on(pipe.read(buf)))
+    }
+}
+impl Seek for SharedPipe {
+    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
+        Err(not_seekable("a pipe"))
+    }
+}
+impl Write for SharedPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.direction != Direction::Write {
+            return Err(io::Error::other("can not write to a read pipe"));
+        }
+        self.with_pipe(|mut pipe| block_on(pipe.write(buf)))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WasiFile for SharedPipe {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+    fn last_modified(&self) -> u64 {
+        0
+    }
+    fn created_time(&self) -> u64 {
+        0
+    }
+    fn size(&self) -> u64 {
+        0
+    }
+    fn set_len(&mut self, _new_size: u64) -> Result<(), WasiFsError> {
+        Err(WasiFsError::PermissionDenied)
+    }
+    fn unlink(&mut self) -> Result<(), WasiFsError> {
+        Ok(())
+    }
+
+    fn bytes_available(&self) -> Result<usize, WasiFsError> {
+        Ok(0)
+    }
+}
@@ -0,0 +1,135 @@
+//! Fanning out a single output stream to two readers, see [`WasiStdout::tee`].
+
+use bytes::{Buf, BytesMut};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use tokio::io::{self, AsyncRead, ReadBuf};
+
+use super::pipe::Mutex;
+use super::WasiStdout;
+
+/// One of the two readers returned by [`WasiStdout::tee`]. Each one gets every byte the module
+/// writes, and both get EOF once the stream closes.
+pub struct TeeReader {
+    shared: Arc<Mutex<Shared>>,
+    index: usize,
+}
+
+struct Shared {
+    source: WasiStdout,
+    /// The bytes pulled from `source` that each reader hasn't read yet.
+    queues: [BytesMut; 2],
+    /// If a reader is waiting for the other one to make room in its queue, or for the other
+    /// one to pull from `source`, this is its waker.
+    wakers: [Option<Waker>; 2],
+    /// Whether each reader has been dropped, so its queue doesn't need to be filled anymore.
+    dropped: [bool; 2],
+    /// Whether `source` has hit EOF.
+    eof: bool,
+    /// The most bytes a queue can hold before pulling from `source` waits on its reader.
+    max_queue_len: usize,
+}
+
+impl Shared {
+    fn wake(&mut self, index: usize) {
+        if let Some(waker) = self.wakers[index].take() {
+            waker.wake();
+        }
+    }
+
+    fn poll_read(
+        &mut self,
+        index: usize,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let other = 1 - index;
+        loop {
+            let queue = &mut self.queues[index];
+            if queue.has_remaining() {
+                let len = queue.remaining().min(buf.remaining());
+                buf.put_slice(&queue[..len]);
+                queue.advance(len);
+                // the other reader might be waiting for room in this queue
+                self.wake(other);
+                return Poll::Ready(Ok(()));
+            }
+            if self.eof {
+                return Poll::Ready(Ok(()));
+            }
+
+            // Our queue is empty, so pull the next chunk from the source, but only as much as
+            // the other reader has room for.
+            let room = if self.dropped[other] {
+                self.max_queue_len
+            } else {
+                self.max_queue_len.saturating_sub(self.queues[other].len())
+            };
+            if room == 0 {
+                self.wakers[index] = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let mut chunk = vec![0; room];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut self.source).poll_read(cx, &mut chunk_buf) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    // the pipe only wakes the last reader, so make sure the other one can
+                    // pass it on
+                    self.wakers[index] = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+            let filled = chunk_buf.filled();
+            if filled.is_empty() {
+                self.eof = true;
+            } else {
+                self.queues[index].extend_from_slice(filled);
+                if !self.dropped[other] {
+                    self.queues[other].extend_from_slice(filled);
+                }
+            }
+            self.wake(other);
+        }
+    }
+}
+
+impl AsyncRead for TeeReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.shared.lock().poll_read(self.index, cx, buf)
+    }
+}
+
+impl Drop for TeeReader {
+    fn drop(&mut self) {
+        let mut shared = self.shared.lock();
+        shared.dropped[self.index] = true;
+        shared.queues[self.index] = BytesMut::new();
+        // the other reader might be waiting for room in this queue
+        shared.wake(1 - self.index);
+    }
+}
+
+pub(crate) fn tee(source: WasiStdout) -> (TeeReader, TeeReader) {
+    let max_queue_len = source.inner.max_buf_size();
+    let shared = Arc::new(Mutex::new(Shared {
+        source,
+        queues: [BytesMut::new(), BytesMut::new()],
+        wakers: [None, None],
+        dropped: [false, false],
+        eof: false,
+        max_queue_len,
+    }));
+    let a = TeeReader {
+        shared: shared.clone(),
+        index: 0,
+    };
+    let b = TeeReader { shared, index: 1 };
+    (a, b)
+}
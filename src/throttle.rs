@@ -0,0 +1,88 @@
+//! Pacing what's written to stdin, see [`WasiStdin::throttle`].
+
+use std::convert::TryFrom;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{self, AsyncWrite};
+use tokio::time::{Instant, Sleep};
+
+use super::WasiStdin;
+
+/// Stdin that lets bytes through no faster than a set rate, from [`WasiStdin::throttle`].
+pub struct ThrottledStdin {
+    stdin: WasiStdin,
+    bytes_per_sec: u64,
+    /// The most that's written at once, a hundredth of a second's worth.
+    chunk: usize,
+    /// When the last chunk was let through, which the next one is timed from.
+    last: Option<Instant>,
+    /// The chunk that's waiting for its turn, and how big it is.
+    pending: Option<(usize, Pin<Box<Sleep>>)>,
+}
+
+impl ThrottledStdin {
+    pub(crate) fn new(stdin: WasiStdin, bytes_per_sec: NonZeroU64) -> Self {
+        let bytes_per_sec = bytes_per_sec.get();
+        Self {
+            stdin,
+            bytes_per_sec,
+            chunk: usize::try_from(bytes_per_sec / 100)
+                .unwrap_or(usize::MAX)
+                .max(1),
+            last: None,
+            pending: None,
+        }
+    }
+
+    /// How long `len` bytes take at this rate, rounded up.
+    fn time_for(&self, len: usize) -> Duration {
+        let rate = u128::from(self.bytes_per_sec);
+        let nanos = (len as u128 * 1_000_000_000).div_ceil(rate);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Get back the underlying stdin, without the throttling.
+    pub fn into_inner(self) -> WasiStdin {
+        self.stdin
+    }
+}
+
+impl AsyncWrite for ThrottledStdin {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Pin::new(&mut self.stdin).poll_write(cx, buf);
+        }
+        let this = &mut *self;
+        if this.pending.is_none() {
+            let len = buf.len().min(this.chunk);
+            // time passing without writes doesn't build up a burst
+            let now = Instant::now();
+            let from = this.last.map_or(now, |last| last.max(now));
+            let sleep = tokio::time::sleep_until(from + this.time_for(len));
+            this.pending = Some((len, Box::pin(sleep)));
+        }
+        let (len, sleep) = this.pending.as_mut().expect("set above");
+        ready!(sleep.as_mut().poll(cx));
+        let len = (*len).min(buf.len());
+        let deadline = sleep.deadline();
+        let res = ready!(Pin::new(&mut this.stdin).poll_write(cx, &buf[..len]));
+        this.pending = None;
+        this.last = Some(deadline);
+        Poll::Ready(res)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_shutdown(cx)
+    }
+}
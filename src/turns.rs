@@ -0,0 +1,147 @@
+//! Modules that take turns with the task running their process, see
+//! [`WasiProcessBuilder::yield_interval`](super::WasiProcessBuilder::yield_interval).
+
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task;
+use wasmer::{AsStoreMut, Function, FunctionEnvMut, Imports, RuntimeError};
+use wasmer_wasi::types::wasi::Errno;
+use wasmer_wasi::{WasiEnv, WasiFunctionEnv};
+
+use super::deterministic::NAMESPACES;
+use super::{stdio, Stop, STDIO};
+
+/// Replace `sched_yield` with one that hands the module's turn back to its process every so
+/// often, if the process has [`Turns`].
+pub(crate) fn sched_yield(
+    store: &mut impl AsStoreMut,
+    env: &WasiFunctionEnv,
+    imports: &mut Imports,
+) {
+    let sched_yield = |_: FunctionEnvMut<WasiEnv>| -> Result<i32, RuntimeError> {
+        if let Ok(Some(turns)) = STDIO.try_with(|stdio| stdio.turns.clone()) {
+            turns.yield_turn()?;
+        }
+        Ok(Errno::Success as i32)
+    };
+    for namespace in NAMESPACES {
+        let function = Function::new_typed_with_env(store, &env.env, sched_yield);
+        imports.define(namespace, "sched_yield", function);
+    }
+}
+
+/// The turns a module takes with the task running its process: the module runs until every
+/// `interval`th `sched_yield`, then waits there until the task has let the rest of the runtime
+/// have a turn and been polled again.
+#[derive(Debug, Clone)]
+pub(crate) struct Turns(Arc<TurnsInner>);
+
+#[derive(Debug)]
+struct TurnsInner {
+    interval: u64,
+    /// How many times the module has called `sched_yield`.
+    calls: AtomicU64,
+    /// How many turns the module has handed back.
+    yielded: watch::Sender<u64>,
+    /// How many turns the module has been given back, which it waits for to catch up with
+    /// `yielded`.
+    resumed: watch::Sender<u64>,
+    /// Set once the process is dropped, so the module doesn't wait for a turn that won't come.
+    dropped: AtomicBool,
+    paused: watch::Receiver<bool>,
+    stop: watch::Receiver<Option<Stop>>,
+}
+
+impl Turns {
+    pub fn new(
+        interval: NonZeroU64,
+        paused: watch::Receiver<bool>,
+        stop: watch::Receiver<Option<Stop>>,
+    ) -> Self {
+        Self(Arc::new(TurnsInner {
+            interval: interval.get(),
+            calls: AtomicU64::new(0),
+            yielded: watch::channel(0).0,
+            resumed: watch::channel(0).0,
+            dropped: AtomicBool::new(false),
+            paused,
+            stop,
+        }))
+    }
+
+    /// Called from the module: hand its turn back if it's been `interval` calls since the
+    /// last time, and wait to be given it back. Fails once the process has been stopped, to end
+    /// the module there.
+    fn yield_turn(&self) -> Result<(), RuntimeError> {
+        let calls = self.0.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if !calls.is_multiple_of(self.0.interval) {
+            return Ok(());
+        }
+        let mut turn = 0;
+        self.0.yielded.send_modify(|yielded| {
+            *yielded += 1;
+            turn = *yielded;
+        });
+        let mut resumed = self.0.resumed.subscribe();
+        // the sender is in `self`, so this can't fail
+        let _ = stdio::block_on(resumed.wait_for(|&resumed| resumed >= turn));
+        if let Some(stop) = *self.0.stop.borrow() {
+            return Err(stop.error());
+        }
+        if self.0.dropped.load(Ordering::Acquire) {
+            return Err(RuntimeError::new("the process was dropped"));
+        }
+        Ok(())
+    }
+
+    /// Run the module's `call` on the blocking thread pool, giving it its turns back as it
+    /// hands them in.
+    pub async fn run<F>(self, call: F) -> Result<(), RuntimeError>
+    where
+        F: FnOnce() -> Result<(), RuntimeError> + Send + 'static,
+    {
+        let _resume = ResumeOnDrop(self.0.clone());
+        let mut call = task::spawn_blocking(call);
+        let mut yielded = self.0.yielded.subscribe();
+        loop {
+            tokio::select! {
+                res = &mut call => {
+                    return match res {
+                        Ok(res) => res,
+                        Err(e) => std::panic::resume_unwind(e.into_panic()),
+                    };
+                }
+                // the sender is in `self`, so this can't fail
+                _ = yielded.changed() => {
+                    let turn = *yielded.borrow_and_update();
+                    task::yield_now().await;
+                    self.wait_while_paused().await;
+                    self.0.resumed.send_replace(turn);
+                }
+            }
+        }
+    }
+
+    /// Wait for the process to be resumed, if it's paused, unless it's stopped first.
+    async fn wait_while_paused(&self) {
+        let mut paused = self.0.paused.clone();
+        let mut stop = self.0.stop.clone();
+        tokio::select! {
+            _ = paused.wait_for(|paused| !paused) => {}
+            _ = stop.wait_for(Option::is_some) => {}
+        }
+    }
+}
+
+/// Gives the module its turn back once the process is dropped, for it to end there rather than
+/// wait for a turn that won't come.
+struct ResumeOnDrop(Arc<TurnsInner>);
+
+impl Drop for ResumeOnDrop {
+    fn drop(&mut self) {
+        self.0.dropped.store(true, Ordering::Release);
+        self.0.resumed.send_replace(u64::MAX);
+    }
+}